@@ -17,13 +17,15 @@ use anauuno::channel::thread::ThreadChannel;
 use anauuno::data::Data;
 use anauuno::message::Message;
 use anauuno::service::{MediaSinkService, MediaSinkServiceConfig};
-use anauuno::service::audio::AudioService;
+use anauuno::service::audio::{AudioConfig, AudioService, AudioServiceConfig};
 use anauuno::service::control::ControlService;
-use anauuno::service::input::InputService;
+use anauuno::service::input::{InputHandle, InputService, InputServiceConfig, KeyCode as AaKeyCode};
 use anauuno::service::media_play_back::MediaPlayBackService;
 use anauuno::service::microphone::MicrophoneService;
-use anauuno::service::sensor::SensorService;
-use anauuno::service::video::VideoService;
+use anauuno::service::navigation::{NavigationService, NavigationServiceConfig};
+use anauuno::service::phone_status::PhoneStatusService;
+use anauuno::service::sensor::{SensorService, SensorServiceConfig};
+use anauuno::service::video::{VideoService, VideoServiceConfig};
 use anauuno::stream::rusb::RUSBStream;
 use anauuno::stream::tcp::TcpStream;
 use anauuno::tls::openssl::OpenSSLTlsStream;
@@ -59,6 +61,7 @@ pub struct State {
     is_surface_configured: bool,
     window: Arc<Window>,
     context: Arc<ConnectionContext>,
+    input: InputHandle,
     pipeline: Option<gstreamer::Pipeline>,
     appsink: Option<gstreamer_app::AppSink>,
     video_texture: Option<wgpu::Texture>,
@@ -71,7 +74,7 @@ pub struct State {
 impl State {
     // We don't need this to be async right now,
     // but we will in the next tutorial
-    pub async fn new(window: Arc<Window>, context: Arc<ConnectionContext>) -> anyhow::Result<Self> {
+    pub async fn new(window: Arc<Window>, context: Arc<ConnectionContext>, input: InputHandle) -> anyhow::Result<Self> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -129,6 +132,7 @@ impl State {
             is_surface_configured: false,
             window,
             context,
+            input,
             pipeline: None,
             appsink: None,
             video_texture: None,
@@ -435,40 +439,41 @@ impl State {
 
     fn handle_key(&self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
 
-        let key_code_id = match code {
-            KeyCode::KeyH => 3,
-            KeyCode::KeyB => 4,
+        let key_code = match code {
+            KeyCode::KeyH => Some(AaKeyCode::KeycodeHome),
+            KeyCode::KeyB => Some(AaKeyCode::KeycodeBack),
 
-            KeyCode::ArrowUp => 19,
-            KeyCode::ArrowDown => 20,
-            KeyCode::ArrowLeft => 21,
-            KeyCode::ArrowRight => 22,
-            KeyCode::Enter => 23,
+            KeyCode::ArrowUp => Some(AaKeyCode::KeycodeDPadUp),
+            KeyCode::ArrowDown => Some(AaKeyCode::KeycodeDPadDown),
+            KeyCode::ArrowLeft => Some(AaKeyCode::KeycodeDPadLeft),
+            KeyCode::ArrowRight => Some(AaKeyCode::KeycodeDPadRight),
+            KeyCode::Enter => Some(AaKeyCode::KeycodeDPadCenter),
 
             KeyCode::Digit1 => {
                 if is_pressed {
-                    let mut commands = self.context.commands().lock().unwrap();
-                    commands.send_rotary_event(-1);
+                    self.input.send_rotary_event(-1);
                 }
 
-                0
+                None
             },
             KeyCode::Digit2 => {
                 if is_pressed {
-                    let mut commands = self.context.commands().lock().unwrap();
-                    commands.send_rotary_event(1);
+                    self.input.send_rotary_event(1);
                 }
 
-                0
+                None
             },
-            _ => 0,
+            _ => None,
         };
 
-        if key_code_id != 0 {
+        if let Some(key_code) = key_code {
             //println!("Key {:?} pressed: {}", code, is_pressed);
 
-            let mut commands = self.context.commands().lock().unwrap();
-            commands.send_key_event(key_code_id, is_pressed);
+            if is_pressed {
+                self.input.key_down(key_code, 0);
+            } else {
+                self.input.key_up(key_code, 0);
+            }
         }
 
         match (code, is_pressed) {
@@ -485,14 +490,16 @@ impl State {
 pub struct App {
     state: Option<State>,
     context: Arc<ConnectionContext>,
+    input: InputHandle,
     receiver: Option<std::sync::mpsc::Receiver<Vec<u8>>>,
 }
 
 impl App {
-    pub fn new(context: Arc<ConnectionContext>, receiver: std::sync::mpsc::Receiver<Vec<u8>>) -> Self {
+    pub fn new(context: Arc<ConnectionContext>, input: InputHandle, receiver: std::sync::mpsc::Receiver<Vec<u8>>) -> Self {
         Self {
             state: None,
             context,
+            input,
             receiver: Some(receiver),
         }
     }
@@ -506,7 +513,7 @@ impl ApplicationHandler<State> for App {
         let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
 
         let receiver = self.receiver.take().expect("Receiver already taken");
-        let mut state = pollster::block_on(State::new(window.clone(), Arc::clone(&self.context))).unwrap();
+        let mut state = pollster::block_on(State::new(window.clone(), Arc::clone(&self.context), self.input.clone())).unwrap();
 
         use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawWindowHandle};
         let window_handle = window.window_handle().expect("Failed to get window handle").as_raw();
@@ -777,15 +784,20 @@ fn main() -> rusb::Result<()> {
     //let stream = RUSBStream::new(handle, 0x81, 0x01);
     let stream = anauuno::stream::tcp::TcpStream::new(stream);
     let stream = OpenSSLTlsStream::new(stream);
+    let input_service = InputService::new(InputServiceConfig::default(), Arc::clone(&context));
+    let input = input_service.handle();
+
     let mut connection = Connection::new(stream, Arc::clone(&context))
         .add_service(ThreadChannel::new(ControlService::new(Arc::clone(&context))))
-        .add_service(ThreadChannel::new(SensorService::new(Arc::clone(&context))))
-        .add_service(ThreadChannel::new(VideoService::new(sender, Arc::clone(&context))))
-        .add_service(ThreadChannel::new(InputService::new(Arc::clone(&context))))
-        .add_service(ThreadChannel::new(AudioService::new(Arc::clone(&context))))
-        .add_service(ThreadChannel::new(AudioService::new(Arc::clone(&context))))
-        .add_service(ThreadChannel::new(AudioService::new(Arc::clone(&context))))
-        .add_service(ThreadChannel::new(MicrophoneService::new(Arc::clone(&context))))
+        .add_service(ThreadChannel::new(SensorService::new(SensorServiceConfig::default(), Arc::clone(&context))))
+        .add_service(ThreadChannel::new(VideoService::new(VideoServiceConfig::default(), sender, Arc::clone(&context))))
+        .add_service(ThreadChannel::new(input_service))
+        .add_service(ThreadChannel::new(AudioService::new(AudioServiceConfig::speech(), Arc::clone(&context))))
+        .add_service(ThreadChannel::new(AudioService::new(AudioServiceConfig::system(), Arc::clone(&context))))
+        .add_service(ThreadChannel::new(AudioService::new(AudioServiceConfig::media(), Arc::clone(&context))))
+        .add_service(ThreadChannel::new(MicrophoneService::new(AudioConfig::new(16000, 16, 1), Arc::clone(&context))))
+        .add_service(ThreadChannel::new(NavigationService::new(NavigationServiceConfig::default())))
+        .add_service(ThreadChannel::new(PhoneStatusService::new(Arc::clone(&context))))
         .add_service(ThreadChannel::new(MediaPlayBackService::new(Arc::clone(&context))));
 
     let mut media_service = MediaSinkService::new(MediaSinkServiceConfig {});
@@ -797,7 +809,7 @@ fn main() -> rusb::Result<()> {
 
 
     let event_loop = EventLoop::with_user_event().build().unwrap();
-    let mut app = App::new(context, receiver);
+    let mut app = App::new(context, input, receiver);
 
     event_loop.run_app(&mut app).unwrap();
 
@@ -24,6 +24,10 @@ impl<S: Service + Send + 'static> Channel for BlockingCannel<S> {
         self.service.on_channel_open();
     }
 
+    fn on_add(&mut self, channel_id: u8) {
+        self.service.on_add(channel_id);
+    }
+
     fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service {
         self.service.protobuf_descriptor(channel_id)
     }
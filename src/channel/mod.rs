@@ -8,5 +8,7 @@ pub trait Channel: Send {
 
     fn open(&mut self);
 
+    fn on_add(&mut self, channel_id: u8);
+
     fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service;
 }
@@ -58,6 +58,10 @@ impl<S: Service + Send + 'static> Channel for ThreadChannel<S> {
         });
     }
 
+    fn on_add(&mut self, channel_id: u8) {
+        self.service.lock().unwrap().on_add(channel_id);
+    }
+
     fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service {
         self.service.lock().unwrap().protobuf_descriptor(channel_id)
     }
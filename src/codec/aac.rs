@@ -0,0 +1,269 @@
+use crate::error::Error;
+use crate::service::audio::{AudioConfig, MediaCodecType};
+use symphonia_codec_aac::AacDecoder as SymphoniaAacDecoder;
+use symphonia_core::audio::{Channels, SampleBuffer, SignalSpec};
+use symphonia_core::codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_AAC};
+use symphonia_core::formats::Packet;
+
+const ADTS_SYNC_WORD: u16 = 0xFFF0;
+
+// Decodes the AAC-LC (raw or ADTS framed) media stream into interleaved 16 bit little endian PCM
+pub struct AacDecoder {
+    adts: bool,
+    config: AudioConfig,
+    audio_specific_config: Option<Vec<u8>>,
+    decoder: Option<SymphoniaAacDecoder>,
+    sample_buffer: Option<SampleBuffer<i16>>,
+    // Of the last decoded frame, may differ from `config` (e.g. with an AudioSpecificConfig)
+    spec: Option<SignalSpec>,
+}
+
+impl AacDecoder {
+    pub fn new(codec: MediaCodecType, config: AudioConfig) -> crate::error::Result<Self> {
+        let adts = match codec {
+            MediaCodecType::MediaCodecAudioAACLC => false,
+            MediaCodecType::MediaCodecAudioAACLCADTS => true,
+            _ => return Err(Error::Decode(format!("{:?} is not an AAC codec", codec))),
+        };
+
+        Ok(Self {
+            adts,
+            config,
+            audio_specific_config: None,
+            decoder: None,
+            sample_buffer: None,
+            spec: None,
+        })
+    }
+
+    // Codec config as sent with `MediaMessageType::CodecData` (AudioSpecificConfig)
+    pub fn configure(&mut self, data: &[u8]) -> crate::error::Result<()> {
+        if data.len() < 2 || is_adts(data) {
+            // Nothing to configure, ADTS streams carry their config in every frame header
+            return Ok(());
+        }
+
+        self.audio_specific_config = Some(data.to_vec());
+        self.decoder = None;
+        // The new config may have more channels or longer frames
+        self.sample_buffer = None;
+        self.spec = None;
+
+        Ok(())
+    }
+
+    // Sample rate of the decoded PCM, None until the first frame was decoded
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.spec.map(|spec| spec.rate)
+    }
+
+    pub fn number_of_channels(&self) -> Option<u32> {
+        self.spec.map(|spec| spec.channels.count() as u32)
+    }
+
+    pub fn decode(&mut self, data: &[u8]) -> crate::error::Result<Vec<u8>> {
+        let mut pcm = vec![];
+
+        if self.adts || is_adts(data) {
+            let mut remaining = data;
+
+            while !remaining.is_empty() {
+                let (frame, rest) = split_adts_frame(remaining)?;
+                self.decode_frame(frame, &mut pcm)?;
+                remaining = rest;
+            }
+        } else {
+            self.decode_frame(data, &mut pcm)?;
+        }
+
+        Ok(pcm)
+    }
+
+    fn decode_frame(&mut self, frame: &[u8], pcm: &mut Vec<u8>) -> crate::error::Result<()> {
+        if self.decoder.is_none() {
+            self.decoder = Some(self.create_decoder()?);
+        }
+
+        let decoder = self.decoder.as_mut().unwrap();
+        let decoded = decoder.decode(&Packet::new_from_slice(0, 0, 0, frame))?;
+
+        let frames = decoded.frames();
+        if frames == 0 {
+            return Ok(());
+        }
+
+        let spec = *decoded.spec();
+        let samples = decoded.capacity() * spec.channels.count();
+
+        // Frames of the same stream usually have the same capacity, but don't rely on it
+        if self.sample_buffer.as_ref().is_none_or(|sample_buffer| sample_buffer.capacity() < samples) {
+            self.sample_buffer = Some(SampleBuffer::<i16>::new(decoded.capacity() as u64, spec));
+        }
+        self.spec = Some(spec);
+
+        let sample_buffer = self.sample_buffer.as_mut().unwrap();
+        sample_buffer.copy_interleaved_ref(decoded);
+
+        pcm.reserve(sample_buffer.samples().len() * 2);
+        for sample in sample_buffer.samples() {
+            pcm.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn create_decoder(&self) -> crate::error::Result<SymphoniaAacDecoder> {
+        let mut params = CodecParameters::new();
+        params.for_codec(CODEC_TYPE_AAC)
+            .with_sample_rate(self.config.sample_rate);
+
+        match &self.audio_specific_config {
+            Some(audio_specific_config) => {
+                params.with_extra_data(audio_specific_config.clone().into_boxed_slice());
+            }
+            None => {
+                let channels = match self.config.number_of_channels {
+                    1 => Channels::FRONT_CENTRE,
+                    _ => Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+                };
+                params.with_channels(channels);
+            }
+        }
+
+        Ok(SymphoniaAacDecoder::try_new(&params, &DecoderOptions::default())?)
+    }
+}
+
+fn is_adts(data: &[u8]) -> bool {
+    data.len() >= 2 && u16::from_be_bytes([data[0], data[1]]) & ADTS_SYNC_WORD == ADTS_SYNC_WORD
+}
+
+// Returns the raw AAC payload of the first ADTS frame and the data following it
+fn split_adts_frame(data: &[u8]) -> crate::error::Result<(&[u8], &[u8])> {
+    if data.len() < 7 || !is_adts(data) {
+        return Err(Error::Decode("invalid ADTS header".to_owned()));
+    }
+
+    let protection_absent = data[1] & 0x01 == 1;
+    let header_length = if protection_absent { 7 } else { 9 };
+    let frame_length = (((data[3] & 0x03) as usize) << 11) | ((data[4] as usize) << 3) | ((data[5] as usize) >> 5);
+
+    if frame_length < header_length || frame_length > data.len() {
+        return Err(Error::Decode("invalid ADTS frame length".to_owned()));
+    }
+
+    Ok((&data[header_length..frame_length], &data[frame_length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A silent stereo AAC-LC frame
+    const SILENT_STEREO_FRAME: [u8; 9] = [0x21, 0x00, 0x49, 0x90, 0x02, 0x19, 0x00, 0x23, 0x80];
+
+    // MPEG-4 AAC-LC, 48 kHz, stereo
+    fn adts_header(frame_length: usize, protection_absent: bool) -> Vec<u8> {
+        vec![
+            0xFF,
+            if protection_absent { 0xF1 } else { 0xF0 },
+            0x4C,
+            0x80 | ((frame_length >> 11) & 0x03) as u8,
+            (frame_length >> 3) as u8,
+            ((frame_length & 0x07) << 5) as u8 | 0x1F,
+            0xFC,
+        ]
+    }
+
+    fn adts_frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = adts_header(7 + payload.len(), true);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn adts_frames() {
+        let mut data = adts_frame(&[1, 2, 3]);
+        data.extend(adts_frame(&[4]));
+
+        let (frame, rest) = split_adts_frame(&data).unwrap();
+        assert_eq!(frame, [1, 2, 3]);
+        let (frame, rest) = split_adts_frame(rest).unwrap();
+        assert_eq!(frame, [4]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn adts_frame_with_crc() {
+        // The CRC follows the header
+        let mut data = adts_header(9 + 2, false);
+        data.extend_from_slice(&[0xAB, 0xCD, 5, 6]);
+
+        assert_eq!(split_adts_frame(&data).unwrap(), (&[5, 6][..], &[][..]));
+    }
+
+    #[test]
+    fn long_adts_frame() {
+        // The frame length is spread over three bytes
+        let payload = vec![0x55; 3000];
+        let mut data = adts_frame(&payload);
+        data.push(0xFF);
+
+        let (frame, rest) = split_adts_frame(&data).unwrap();
+        assert_eq!(frame.len(), 3000);
+        assert_eq!(rest, [0xFF]);
+    }
+
+    #[test]
+    fn invalid_adts_frames() {
+        let frame = adts_frame(&[1, 2, 3]);
+
+        // Partial frames
+        assert!(split_adts_frame(&frame[..6]).is_err());
+        assert!(split_adts_frame(&frame[..9]).is_err());
+        assert!(split_adts_frame(&[]).is_err());
+
+        // Bad sync word
+        let mut bad_sync = frame.clone();
+        bad_sync[1] = 0xE1;
+        assert!(!is_adts(&bad_sync));
+        assert!(split_adts_frame(&bad_sync).is_err());
+
+        // Shorter than its header
+        let mut data = adts_header(5, true);
+        data.extend_from_slice(&[0; 4]);
+        assert!(split_adts_frame(&data).is_err());
+    }
+
+    #[test]
+    fn truncated_adts_stream() {
+        let mut decoder = AacDecoder::new(MediaCodecType::MediaCodecAudioAACLCADTS, AudioConfig::new(48000, 16, 2)).unwrap();
+
+        let mut data = adts_frame(&SILENT_STEREO_FRAME);
+        data.extend_from_slice(&adts_frame(&SILENT_STEREO_FRAME)[..10]);
+        assert!(decoder.decode(&data).is_err());
+    }
+
+    #[test]
+    fn not_aac() {
+        assert!(AacDecoder::new(MediaCodecType::MediaCodecAudioPCM, AudioConfig::new(48000, 16, 2)).is_err());
+    }
+
+    #[test]
+    fn decoded_spec() {
+        // Advertised as 16 kHz mono, but the phone configures 48 kHz stereo
+        let mut decoder = AacDecoder::new(MediaCodecType::MediaCodecAudioAACLC, AudioConfig::new(16000, 16, 1)).unwrap();
+        decoder.configure(&[0x11, 0x90]).unwrap();
+        assert_eq!(decoder.sample_rate(), None);
+
+        let pcm = decoder.decode(&SILENT_STEREO_FRAME).unwrap();
+        assert_eq!(decoder.sample_rate(), Some(48000));
+        assert_eq!(decoder.number_of_channels(), Some(2));
+        assert_eq!(pcm.len(), 1024 * 2 * 2);
+        assert!(pcm.iter().all(|byte| *byte == 0));
+
+        // Reconfiguring forgets it until the next frame
+        decoder.configure(&[0x11, 0x90]).unwrap();
+        assert_eq!(decoder.sample_rate(), None);
+    }
+}
@@ -0,0 +1,2 @@
+#[cfg(feature = "aac")]
+pub mod aac;
@@ -1,10 +1,10 @@
 use crate::channel::Channel;
 use crate::data::Data;
-use crate::message::{ControlMessageType, InputMessageType, Message};
+use crate::message::{ControlMessageType, MediaMessageType, Message};
 use crate::protobuf::common::MessageStatus;
 use crate::protobuf::control::{ChannelOpenRequest, ChannelOpenResponse, Service};
-use crate::protobuf::input;
-use crate::protobuf::input::KeyCode;
+use crate::protobuf::media;
+use crate::protobuf::media::VideoFocusMode;
 use crate::stream::Stream;
 use crate::tls::TlsStream;
 use core::any::{Any, TypeId};
@@ -139,7 +139,8 @@ impl<S: Stream, T: TlsStream<S>> Connection<S, T> {
         }
     }
 
-    pub fn add_service<C: Channel + 'static>(mut self, channel: C) -> Self {
+    pub fn add_service<C: Channel + 'static>(mut self, mut channel: C) -> Self {
+        channel.on_add(self.services.len() as u8);
         self.services.push(Box::new(channel));
 
         self
@@ -190,59 +191,18 @@ impl Commands {
         messages
     }
 
-    pub fn send_rotary_event(&mut self, delta: i32) {
-        let mut rel = input::RelativeEvent_Rel::new();
-        rel.keycode = Some(KeyCode::KeycodeRotaryController as u32);
-        rel.delta = Some(delta);
-
-        let mut relative_event = input::RelativeEvent::new();
-        relative_event.data.push(rel);
-
-        let mut report = input::InputReport::new();
-        let ts = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as u64;
-
-        report.set_timestamp(ts);
-        report.relative_event = Some(relative_event).into();
+    // Unsolicited focus change of a display, e.g. when the native UI takes over the cluster
+    pub fn send_video_focus(&mut self, disp_channel_id: u8, focused: bool) {
+        let mut notification = media::VideoFocusNotification::new();
+        notification.set_mode(if focused { VideoFocusMode::Focused } else { VideoFocusMode::Unfocused });
+        notification.set_unsolicited(true);
 
         self.send_message(
             Message::new_with_protobuf_message(
-                3,
+                disp_channel_id,
                 false,
-                report,
-                InputMessageType::InputReport as u16,
-            ),
-            true,
-        );
-    }
-
-    pub fn send_key_event(&mut self, keycode: u32, down: bool) {
-        let mut key = input::Key::new();
-        key.down = Some(down);
-        key.keycode = Some(keycode);
-        key.metastate = Some(0);
-        key.long_press = Some(false);
-
-        let mut key_event = input::KeyEvent::new();
-        key_event.keys.push(key);
-
-        let mut report = input::InputReport::new();
-        let ts = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as u64;
-
-        report.set_timestamp(ts);
-        report.key_event = Some(key_event).into();
-
-        self.send_message(
-            Message::new_with_protobuf_message(
-                3,
-                false,
-                report,
-                InputMessageType::InputReport as u16,
+                notification,
+                MediaMessageType::VideoFocusNotification as u16,
             ),
             true,
         );
@@ -253,6 +213,8 @@ pub struct ConnectionContext {
     app_data: BTreeMap<TypeId, Box<dyn Any + Send + Sync>>,
     commands: Mutex<Commands>,
     service_descriptors: Mutex<Vec<crate::protobuf::control::Service>>,
+    // display_id -> channel of the VideoService
+    displays: Mutex<BTreeMap<u32, u8>>,
 }
 
 impl ConnectionContext {
@@ -261,6 +223,7 @@ impl ConnectionContext {
             app_data: BTreeMap::new(),
             commands: Mutex::new(Commands::new()),
             service_descriptors: Mutex::new(vec![]),
+            displays: Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -280,4 +243,13 @@ impl ConnectionContext {
     pub fn get_service_descriptors(&self) -> &Mutex<Vec<Service>> {
         &self.service_descriptors
     }
+
+    pub(crate) fn register_display(&self, display_id: u32, channel_id: u8) {
+        self.displays.lock().unwrap().insert(display_id, channel_id);
+    }
+
+    // Available once the connection has been started
+    pub fn display_channel_id(&self, display_id: u32) -> Option<u8> {
+        self.displays.lock().unwrap().get(&display_id).copied()
+    }
 }
@@ -7,6 +7,9 @@ pub enum Error {
     IoPipe,
     IoOther,
     IoStd(std::io::Error),
+    Decode(String),
+    Config(String),
+    DBus(String),
 }
 
 impl From<rusb::Error> for Error {
@@ -29,6 +32,26 @@ impl From<std::io::Error> for Error {
     }
 }
 
+#[cfg(feature = "aac")]
+impl From<symphonia_core::errors::Error> for Error {
+    fn from(e: symphonia_core::errors::Error) -> Self {
+        match e {
+            symphonia_core::errors::Error::IoError(e) => Error::IoStd(e),
+            e => Error::Decode(e.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "mpris")]
+impl From<zbus::Error> for Error {
+    fn from(e: zbus::Error) -> Self {
+        match e {
+            zbus::Error::InputOutput(e) => Error::IoStd(std::io::Error::new(e.kind(), e.to_string())),
+            e => Error::DBus(e.to_string()),
+        }
+    }
+}
+
 impl From<Error> for std::io::Error {
     fn from(e: Error) -> Self {
         match e {
@@ -37,6 +60,9 @@ impl From<Error> for std::io::Error {
             Error::IoDisconnected => std::io::Error::new(std::io::ErrorKind::NotConnected, "io disconnected"),
             Error::IoPipe => std::io::Error::new(std::io::ErrorKind::BrokenPipe, "io pipe"),
             Error::IoOther => std::io::Error::new(std::io::ErrorKind::Other, "io error"),
+            Error::Decode(e) => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+            Error::Config(e) => std::io::Error::new(std::io::ErrorKind::InvalidInput, e),
+            Error::DBus(e) => std::io::Error::other(e),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,712 @@
+use crate::input::keymap::KeyMap;
+use crate::input::multitouch::MultiTouchTracker;
+use crate::service::input::{InputHandle, KeyCode, TouchSurface};
+use ::evdev::raw_stream::RawDevice;
+use ::evdev::{AbsoluteAxisCode, EventSummary, InputEvent, KeyCode as EvdevKeyCode, RelativeAxisCode, SynchronizationCode};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+pub use ::evdev;
+
+// How often held keys are checked for long presses while no events arrive
+const TICK_INTERVAL_MS: i32 = 50;
+
+#[derive(Clone, Copy)]
+struct Slot {
+    tracking_id: i32,
+    x: i32,
+    y: i32,
+    dirty: bool,
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Self { tracking_id: -1, x: 0, y: 0, dirty: false }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct AxisRange {
+    minimum: i32,
+    maximum: i32,
+}
+
+impl AxisRange {
+    fn scale(&self, value: i32, size: u32) -> u32 {
+        let range = (self.maximum as i64 - self.minimum as i64 + 1).max(1);
+        let value = (value as i64 - self.minimum as i64).clamp(0, range - 1);
+
+        (value * size as i64 / range) as u32
+    }
+}
+
+// Feeds the events of a Linux input device into an InputService: multitouch (protocol B) and
+// single touch panels go to the touchscreen (or touchpad), REL_DIAL/REL_WHEEL to the rotary
+// controller and keys either through a KeyMap or a default mapping of common keys.
+// Use one bridge per device.
+pub struct EvdevBridge {
+    handle: InputHandle,
+    surface: TouchSurface,
+    tracker: MultiTouchTracker,
+    keymap: Option<KeyMap>,
+    multitouch: bool,
+    slots: Vec<Slot>,
+    current_slot: usize,
+    // Single touch devices only report ABS_X/ABS_Y and BTN_TOUCH
+    single: Slot,
+    x_range: Option<AxisRange>,
+    y_range: Option<AxisRange>,
+    dropped: bool,
+}
+
+impl EvdevBridge {
+    pub fn new(handle: InputHandle) -> Self {
+        Self {
+            tracker: MultiTouchTracker::new(handle.clone()),
+            handle,
+            surface: TouchSurface::Touchscreen,
+            keymap: None,
+            multitouch: false,
+            slots: vec![Slot::default()],
+            current_slot: 0,
+            single: Slot::default(),
+            x_range: None,
+            y_range: None,
+            dropped: false,
+        }
+    }
+
+    pub fn with_surface(mut self, surface: TouchSurface) -> Self {
+        self.surface = surface;
+        self.tracker = MultiTouchTracker::for_surface(self.handle.clone(), surface);
+
+        self
+    }
+
+    pub fn with_keymap(mut self, keymap: KeyMap) -> Self {
+        self.keymap = Some(keymap);
+
+        self
+    }
+
+    // Raw coordinates of the device, they are scaled to the size of the configured surface
+    pub fn set_axis_ranges(&mut self, x: (i32, i32), y: (i32, i32)) {
+        self.x_range = Some(AxisRange { minimum: x.0, maximum: x.1 });
+        self.y_range = Some(AxisRange { minimum: y.0, maximum: y.1 });
+    }
+
+    // Takes the axis ranges and touch type from the device
+    pub fn configure(&mut self, device: &RawDevice) -> crate::error::Result<()> {
+        let mut x = None;
+        let mut y = None;
+
+        for (axis, info) in device.get_absinfo()? {
+            let range = (info.minimum(), info.maximum());
+
+            match axis {
+                AbsoluteAxisCode::ABS_MT_POSITION_X => {
+                    self.multitouch = true;
+                    x = Some(range);
+                }
+                AbsoluteAxisCode::ABS_MT_POSITION_Y => {
+                    self.multitouch = true;
+                    y = Some(range);
+                }
+                AbsoluteAxisCode::ABS_X => {
+                    x = x.or(Some(range));
+                }
+                AbsoluteAxisCode::ABS_Y => {
+                    y = y.or(Some(range));
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some(x), Some(y)) = (x, y) {
+            self.set_axis_ranges(x, y);
+        }
+
+        Ok(())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::error::Result<RawDevice> {
+        Ok(RawDevice::open(path)?)
+    }
+
+    // Reads events until the device disappears (Error::IoDisconnected) or fails
+    pub fn run(&mut self, device: &mut RawDevice) -> crate::error::Result<()> {
+        self.configure(device)?;
+
+        println!("EvdevBridge: Reading {}", device.name().unwrap_or("unnamed device"));
+
+        loop {
+            let mut poll_fd = libc::pollfd {
+                fd: device.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            let ready = unsafe { libc::poll(&mut poll_fd, 1, TICK_INTERVAL_MS) };
+            if ready < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(e.into());
+            }
+
+            if poll_fd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                self.tracker.cancel();
+                return Err(crate::error::Error::IoDisconnected);
+            }
+
+            if ready > 0 {
+                let events: Vec<InputEvent> = match device.fetch_events() {
+                    Ok(events) => events.collect(),
+                    Err(e) if e.raw_os_error() == Some(libc::ENODEV) => {
+                        self.tracker.cancel();
+                        return Err(crate::error::Error::IoDisconnected);
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => vec![],
+                    Err(e) => return Err(e.into()),
+                };
+
+                for event in &events {
+                    self.handle_event(event);
+                }
+            }
+
+            self.tick();
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if let Some(keymap) = self.keymap.as_mut() {
+            keymap.tick();
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &InputEvent) {
+        match event.destructure() {
+            EventSummary::Synchronization(_, SynchronizationCode::SYN_DROPPED, _) => {
+                // The kernel buffer overflowed, the contacts can't be trusted anymore
+                self.tracker.cancel();
+                self.slots = vec![Slot::default(); self.slots.len()];
+                self.single = Slot::default();
+                self.dropped = true;
+            }
+            EventSummary::Synchronization(_, SynchronizationCode::SYN_REPORT, _) => {
+                if self.dropped {
+                    self.dropped = false;
+                    return;
+                }
+
+                self.sync_touch();
+            }
+            _ if self.dropped => {}
+            EventSummary::AbsoluteAxis(_, axis, value) => {
+                self.handle_absolute(axis, value);
+            }
+            EventSummary::Key(_, EvdevKeyCode::BTN_TOUCH, value) if !self.multitouch => {
+                self.single.tracking_id = if value != 0 { 0 } else { -1 };
+                self.single.dirty = true;
+            }
+            EventSummary::Key(_, _, 2) => {
+                // Autorepeat, the phone does its own
+            }
+            EventSummary::Key(_, key, value) => {
+                self.handle_key(key, value != 0);
+            }
+            EventSummary::RelativeAxis(_, axis, value) => {
+                self.handle_relative(axis, value);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_absolute(&mut self, axis: AbsoluteAxisCode, value: i32) {
+        match axis {
+            AbsoluteAxisCode::ABS_MT_SLOT => {
+                self.multitouch = true;
+                self.current_slot = value.max(0) as usize;
+
+                if self.slots.len() <= self.current_slot {
+                    self.slots.resize(self.current_slot + 1, Slot::default());
+                }
+            }
+            AbsoluteAxisCode::ABS_MT_TRACKING_ID => {
+                self.multitouch = true;
+
+                let slot = &mut self.slots[self.current_slot];
+                slot.tracking_id = value;
+                slot.dirty = true;
+            }
+            AbsoluteAxisCode::ABS_MT_POSITION_X => {
+                self.multitouch = true;
+
+                let slot = &mut self.slots[self.current_slot];
+                slot.x = value;
+                slot.dirty = true;
+            }
+            AbsoluteAxisCode::ABS_MT_POSITION_Y => {
+                self.multitouch = true;
+
+                let slot = &mut self.slots[self.current_slot];
+                slot.y = value;
+                slot.dirty = true;
+            }
+            AbsoluteAxisCode::ABS_X if !self.multitouch => {
+                self.single.x = value;
+                self.single.dirty = true;
+            }
+            AbsoluteAxisCode::ABS_Y if !self.multitouch => {
+                self.single.y = value;
+                self.single.dirty = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn sync_touch(&mut self) {
+        let size = self.handle.touch_size(self.surface);
+
+        let slots: Vec<(usize, Slot)> = if self.multitouch {
+            self.slots.iter().copied().enumerate().collect()
+        } else {
+            vec![(0, self.single)]
+        };
+
+        let mut changed = false;
+
+        for (index, slot) in slots {
+            if !slot.dirty {
+                continue;
+            }
+            changed = true;
+
+            if slot.tracking_id < 0 {
+                self.tracker.release(index as u32);
+                continue;
+            }
+
+            let (x, y) = match (size, self.x_range, self.y_range) {
+                (Some((width, height)), Some(x_range), Some(y_range)) => (x_range.scale(slot.x, width), y_range.scale(slot.y, height)),
+                _ => (slot.x.max(0) as u32, slot.y.max(0) as u32),
+            };
+
+            self.tracker.update(index as u32, x, y);
+        }
+
+        for slot in self.slots.iter_mut() {
+            slot.dirty = false;
+        }
+        self.single.dirty = false;
+
+        if changed {
+            self.tracker.sync();
+        }
+    }
+
+    fn handle_key(&mut self, key: EvdevKeyCode, pressed: bool) {
+        if let Some(keymap) = self.keymap.as_mut()
+            && keymap.button(&format!("{:?}", key), pressed) {
+            return;
+        }
+
+        // Finger and tool reports of touch devices
+        if (EvdevKeyCode::BTN_TOOL_PEN.code()..=EvdevKeyCode::BTN_TOOL_QUADTAP.code()).contains(&key.code()) {
+            return;
+        }
+
+        let Some(keycode) = default_keycode(key) else {
+            return;
+        };
+
+        if pressed {
+            self.handle.key_down(keycode, 0);
+        } else {
+            self.handle.key_up(keycode, 0);
+        }
+    }
+
+    fn handle_relative(&mut self, axis: RelativeAxisCode, value: i32) {
+        if let Some(keymap) = self.keymap.as_mut()
+            && keymap.rotary(&format!("{:?}", axis), value) {
+            return;
+        }
+
+        if axis == RelativeAxisCode::REL_DIAL || axis == RelativeAxisCode::REL_WHEEL {
+            self.handle.send_rotary_event(value);
+        }
+    }
+}
+
+// Keys commonly found on head unit buttons and steering wheels
+fn default_keycode(key: EvdevKeyCode) -> Option<KeyCode> {
+    let keycode = match key {
+        EvdevKeyCode::KEY_UP => KeyCode::KeycodeDPadUp,
+        EvdevKeyCode::KEY_DOWN => KeyCode::KeycodeDPadDown,
+        EvdevKeyCode::KEY_LEFT => KeyCode::KeycodeDPadLeft,
+        EvdevKeyCode::KEY_RIGHT => KeyCode::KeycodeDPadRight,
+        EvdevKeyCode::KEY_ENTER | EvdevKeyCode::KEY_OK | EvdevKeyCode::KEY_SELECT => KeyCode::KeycodeDPadCenter,
+        EvdevKeyCode::KEY_HOME | EvdevKeyCode::KEY_HOMEPAGE => KeyCode::KeycodeHome,
+        EvdevKeyCode::KEY_BACK | EvdevKeyCode::KEY_ESC => KeyCode::KeycodeBack,
+        EvdevKeyCode::KEY_PLAYPAUSE => KeyCode::KeycodeMediaPlayPause,
+        EvdevKeyCode::KEY_PLAY => KeyCode::KeycodeMediaPlay,
+        EvdevKeyCode::KEY_PAUSE => KeyCode::KeycodeMediaPause,
+        EvdevKeyCode::KEY_STOPCD => KeyCode::KeycodeMediaStop,
+        EvdevKeyCode::KEY_NEXTSONG => KeyCode::KeycodeMediaNext,
+        EvdevKeyCode::KEY_PREVIOUSSONG => KeyCode::KeycodeMediaPrevious,
+        EvdevKeyCode::KEY_FASTFORWARD => KeyCode::KeycodeMediaFastForward,
+        EvdevKeyCode::KEY_REWIND => KeyCode::KeycodeMediaRewind,
+        EvdevKeyCode::KEY_PHONE => KeyCode::KeycodeCall,
+        EvdevKeyCode::KEY_SEARCH => KeyCode::KeycodeSearch,
+        EvdevKeyCode::KEY_VOICECOMMAND => KeyCode::KeycodeVoiceAssist,
+        EvdevKeyCode::KEY_MENU => KeyCode::KeycodeMenu,
+        EvdevKeyCode::KEY_TAB => KeyCode::KeycodeTab,
+        EvdevKeyCode::KEY_SPACE => KeyCode::KeycodeSpace,
+        EvdevKeyCode::KEY_BACKSPACE => KeyCode::KeycodeDel,
+        _ => return None,
+    };
+
+    Some(keycode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionContext;
+    use crate::input::keymap::{KeyBinding, KeyMapConfig};
+    use crate::service::input::testing::{input_handle, sent, Sent};
+    use crate::service::input::PointerAction;
+    use ::evdev::{AbsoluteAxisEvent, KeyEvent, RelativeAxisEvent, SynchronizationEvent};
+    use std::sync::Arc;
+
+    fn abs(axis: AbsoluteAxisCode, value: i32) -> InputEvent {
+        *AbsoluteAxisEvent::new(axis, value)
+    }
+
+    fn key(key: EvdevKeyCode, value: i32) -> InputEvent {
+        *KeyEvent::new(key, value)
+    }
+
+    fn rel(axis: RelativeAxisCode, value: i32) -> InputEvent {
+        *RelativeAxisEvent::new(axis, value)
+    }
+
+    fn syn(code: SynchronizationCode) -> InputEvent {
+        *SynchronizationEvent::new(code, 0)
+    }
+
+    // Raw coordinates 0..=999 on the 100x100 touchpad
+    fn bridge() -> (EvdevBridge, Arc<ConnectionContext>) {
+        let (handle, context) = input_handle();
+
+        let mut bridge = EvdevBridge::new(handle).with_surface(TouchSurface::Touchpad);
+        bridge.set_axis_ranges((0, 999), (0, 999));
+
+        (bridge, context)
+    }
+
+    // Feeds the events followed by SYN_REPORT
+    fn frame(bridge: &mut EvdevBridge, events: &[InputEvent]) {
+        for event in events {
+            bridge.handle_event(event);
+        }
+
+        bridge.handle_event(&syn(SynchronizationCode::SYN_REPORT));
+    }
+
+    #[test]
+    fn multitouch_slots() {
+        let (mut bridge, context) = bridge();
+
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_SLOT, 0),
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 10),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 500),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, 250),
+        ]);
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_SLOT, 1),
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 11),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 100),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, 900),
+        ]);
+        // The slot stays selected until the next ABS_MT_SLOT
+        frame(&mut bridge, &[abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 200)]);
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_SLOT, 0),
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1),
+        ]);
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_SLOT, 1),
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1),
+        ]);
+
+        assert_eq!(sent(&context), vec![
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 50, 25)]),
+            Sent::Touchpad(PointerAction::TouchActionPointerDown, 1, vec![(0, 50, 25), (1, 10, 90)]),
+            Sent::Touchpad(PointerAction::TouchActionMove, 0, vec![(0, 50, 25), (1, 20, 90)]),
+            Sent::Touchpad(PointerAction::TouchActionPointerUp, 0, vec![(0, 50, 25), (1, 20, 90)]),
+            Sent::Touchpad(PointerAction::TouchActionUp, 0, vec![(1, 20, 90)]),
+        ]);
+    }
+
+    #[test]
+    fn new_tracking_id_in_slot() {
+        let (mut bridge, context) = bridge();
+
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 1),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 100),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, 100),
+        ]);
+        frame(&mut bridge, &[abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1)]);
+        // The next finger in the same slot
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 2),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 300),
+        ]);
+
+        assert_eq!(sent(&context), vec![
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 10, 10)]),
+            Sent::Touchpad(PointerAction::TouchActionUp, 0, vec![(0, 10, 10)]),
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 30, 10)]),
+        ]);
+    }
+
+    #[test]
+    fn single_touch() {
+        let (mut bridge, context) = bridge();
+
+        frame(&mut bridge, &[
+            key(EvdevKeyCode::BTN_TOUCH, 1),
+            key(EvdevKeyCode::BTN_TOOL_FINGER, 1),
+            abs(AbsoluteAxisCode::ABS_X, 400),
+            abs(AbsoluteAxisCode::ABS_Y, 600),
+        ]);
+        frame(&mut bridge, &[abs(AbsoluteAxisCode::ABS_X, 410)]);
+        frame(&mut bridge, &[
+            key(EvdevKeyCode::BTN_TOUCH, 0),
+            key(EvdevKeyCode::BTN_TOOL_FINGER, 0),
+        ]);
+
+        assert_eq!(sent(&context), vec![
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 40, 60)]),
+            Sent::Touchpad(PointerAction::TouchActionMove, 0, vec![(0, 41, 60)]),
+            Sent::Touchpad(PointerAction::TouchActionUp, 0, vec![(0, 41, 60)]),
+        ]);
+    }
+
+    #[test]
+    fn syn_dropped() {
+        let (mut bridge, context) = bridge();
+
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 1),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 100),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, 100),
+        ]);
+
+        // Everything up to the next SYN_REPORT is incomplete and ignored
+        bridge.handle_event(&syn(SynchronizationCode::SYN_DROPPED));
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 900),
+            key(EvdevKeyCode::KEY_NEXTSONG, 1),
+        ]);
+
+        frame(&mut bridge, &[
+            abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 2),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 500),
+            abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, 500),
+        ]);
+
+        assert_eq!(sent(&context), vec![
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 10, 10)]),
+            Sent::Touchpad(PointerAction::TouchActionCancel, 0, vec![(0, 10, 10)]),
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 50, 50)]),
+        ]);
+    }
+
+    #[test]
+    fn keys() {
+        let (mut bridge, context) = bridge();
+
+        frame(&mut bridge, &[key(EvdevKeyCode::KEY_NEXTSONG, 1)]);
+        // Autorepeat
+        frame(&mut bridge, &[key(EvdevKeyCode::KEY_NEXTSONG, 2)]);
+        frame(&mut bridge, &[key(EvdevKeyCode::KEY_NEXTSONG, 0)]);
+        // Not mapped
+        frame(&mut bridge, &[key(EvdevKeyCode::KEY_A, 1), key(EvdevKeyCode::KEY_A, 0)]);
+        frame(&mut bridge, &[key(EvdevKeyCode::KEY_ESC, 1), key(EvdevKeyCode::KEY_ESC, 0)]);
+
+        assert_eq!(sent(&context), vec![
+            Sent::Key { keycode: KeyCode::KeycodeMediaNext, down: true, long_press: false },
+            Sent::Key { keycode: KeyCode::KeycodeMediaNext, down: false, long_press: false },
+            Sent::Key { keycode: KeyCode::KeycodeBack, down: true, long_press: false },
+            Sent::Key { keycode: KeyCode::KeycodeBack, down: false, long_press: false },
+        ]);
+    }
+
+    #[test]
+    fn relative_axes() {
+        let (mut bridge, context) = bridge();
+
+        frame(&mut bridge, &[rel(RelativeAxisCode::REL_DIAL, 2)]);
+        frame(&mut bridge, &[rel(RelativeAxisCode::REL_WHEEL, -1)]);
+        frame(&mut bridge, &[rel(RelativeAxisCode::REL_X, 5)]);
+
+        assert_eq!(sent(&context), vec![
+            Sent::Relative(KeyCode::KeycodeRotaryController, 2),
+            Sent::Relative(KeyCode::KeycodeRotaryController, -1),
+        ]);
+    }
+
+    #[test]
+    fn keymap_before_default_mapping() {
+        let (handle, context) = input_handle();
+
+        let config = KeyMapConfig {
+            keys: vec![KeyBinding::new("KEY_NEXTSONG", KeyCode::KeycodeMediaFastForward)],
+            rotaries: vec![],
+        };
+        let mut bridge = EvdevBridge::new(handle.clone()).with_keymap(KeyMap::new(config, handle));
+
+        frame(&mut bridge, &[key(EvdevKeyCode::KEY_NEXTSONG, 1)]);
+        frame(&mut bridge, &[key(EvdevKeyCode::KEY_PREVIOUSSONG, 1)]);
+
+        assert_eq!(sent(&context), vec![
+            Sent::Key { keycode: KeyCode::KeycodeMediaFastForward, down: true, long_press: false },
+            Sent::Key { keycode: KeyCode::KeycodeMediaPrevious, down: true, long_press: false },
+        ]);
+    }
+
+    #[test]
+    fn default_keycodes() {
+        assert_eq!(default_keycode(EvdevKeyCode::KEY_UP), Some(KeyCode::KeycodeDPadUp));
+        assert_eq!(default_keycode(EvdevKeyCode::KEY_OK), Some(KeyCode::KeycodeDPadCenter));
+        assert_eq!(default_keycode(EvdevKeyCode::KEY_SELECT), Some(KeyCode::KeycodeDPadCenter));
+        assert_eq!(default_keycode(EvdevKeyCode::KEY_PHONE), Some(KeyCode::KeycodeCall));
+        assert_eq!(default_keycode(EvdevKeyCode::KEY_VOICECOMMAND), Some(KeyCode::KeycodeVoiceAssist));
+        assert_eq!(default_keycode(EvdevKeyCode::BTN_LEFT), None);
+    }
+
+    mod uinput {
+        use super::*;
+        use ::evdev::uinput::VirtualDevice;
+        use ::evdev::{AbsInfo, AttributeSet, UinputAbsSetup};
+        use std::time::Duration;
+
+        fn axis(axis: AbsoluteAxisCode, maximum: i32) -> UinputAbsSetup {
+            UinputAbsSetup::new(axis, AbsInfo::new(0, 0, maximum, 0, 0, 0))
+        }
+
+        // Emits the frames into a virtual device and runs a bridge on its event node until
+        // the device is removed
+        fn run(device: VirtualDevice, frames: &[Vec<InputEvent>]) -> Vec<Sent> {
+            let mut device = device;
+
+            let path = (0..50)
+                .find_map(|_| {
+                    let path = device.enumerate_dev_nodes_blocking().unwrap().flatten().next();
+                    if path.is_none() {
+                        std::thread::sleep(Duration::from_millis(20));
+                    }
+                    path
+                })
+                .expect("no event node for the virtual device");
+
+            let (handle, context) = input_handle();
+            let mut raw = EvdevBridge::open(path).unwrap();
+
+            let bridge = std::thread::spawn(move || {
+                let mut bridge = EvdevBridge::new(handle).with_surface(TouchSurface::Touchpad);
+                bridge.run(&mut raw)
+            });
+
+            std::thread::sleep(Duration::from_millis(100));
+            for frame in frames {
+                device.emit(frame).unwrap();
+            }
+            std::thread::sleep(Duration::from_millis(100));
+            drop(device);
+
+            assert!(matches!(bridge.join().unwrap(), Err(crate::error::Error::IoDisconnected)));
+
+            sent(&context)
+        }
+
+        #[test]
+        #[ignore = "needs write access to /dev/uinput"]
+        fn multitouch_device() {
+            let device = VirtualDevice::builder().unwrap()
+                .name("anauuno test touchscreen")
+                .with_keys(&AttributeSet::from_iter([EvdevKeyCode::BTN_TOUCH])).unwrap()
+                .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_SLOT, 9)).unwrap()
+                .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 65535)).unwrap()
+                .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_POSITION_X, 999)).unwrap()
+                .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_POSITION_Y, 999)).unwrap()
+                .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_X, 999)).unwrap()
+                .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_Y, 999)).unwrap()
+                .build().unwrap();
+
+            let sent = run(device, &[
+                vec![
+                    abs(AbsoluteAxisCode::ABS_MT_SLOT, 0),
+                    abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 1),
+                    abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 500),
+                    abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, 250),
+                    key(EvdevKeyCode::BTN_TOUCH, 1),
+                ],
+                vec![
+                    abs(AbsoluteAxisCode::ABS_MT_SLOT, 1),
+                    abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 2),
+                    abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 100),
+                    abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, 900),
+                ],
+                vec![abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1)],
+                vec![
+                    abs(AbsoluteAxisCode::ABS_MT_SLOT, 0),
+                    abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1),
+                    key(EvdevKeyCode::BTN_TOUCH, 0),
+                ],
+            ]);
+
+            // Scaled from the device's axis ranges
+            assert_eq!(sent, vec![
+                Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 50, 25)]),
+                Sent::Touchpad(PointerAction::TouchActionPointerDown, 1, vec![(0, 50, 25), (1, 10, 90)]),
+                Sent::Touchpad(PointerAction::TouchActionPointerUp, 1, vec![(0, 50, 25), (1, 10, 90)]),
+                Sent::Touchpad(PointerAction::TouchActionUp, 0, vec![(0, 50, 25)]),
+            ]);
+        }
+
+        #[test]
+        #[ignore = "needs write access to /dev/uinput"]
+        fn keys_and_dial() {
+            let device = VirtualDevice::builder().unwrap()
+                .name("anauuno test controller")
+                .with_keys(&AttributeSet::from_iter([EvdevKeyCode::KEY_NEXTSONG, EvdevKeyCode::KEY_BACK])).unwrap()
+                .with_relative_axes(&AttributeSet::from_iter([RelativeAxisCode::REL_DIAL])).unwrap()
+                .build().unwrap();
+
+            let sent = run(device, &[
+                vec![key(EvdevKeyCode::KEY_NEXTSONG, 1)],
+                vec![key(EvdevKeyCode::KEY_NEXTSONG, 0)],
+                vec![rel(RelativeAxisCode::REL_DIAL, -3)],
+                vec![key(EvdevKeyCode::KEY_BACK, 1)],
+                vec![key(EvdevKeyCode::KEY_BACK, 0)],
+            ]);
+
+            assert_eq!(sent, vec![
+                Sent::Key { keycode: KeyCode::KeycodeMediaNext, down: true, long_press: false },
+                Sent::Key { keycode: KeyCode::KeycodeMediaNext, down: false, long_press: false },
+                Sent::Relative(KeyCode::KeycodeRotaryController, -3),
+                Sent::Key { keycode: KeyCode::KeycodeBack, down: true, long_press: false },
+                Sent::Key { keycode: KeyCode::KeycodeBack, down: false, long_press: false },
+            ]);
+        }
+    }
+}
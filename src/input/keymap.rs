@@ -0,0 +1,541 @@
+use crate::service::input::{InputHandle, KeyCode};
+use protobuf::{Enum, EnumFull};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// Maps a physical button, `source` is whatever name the input backend uses for it
+// (e.g. "KEY_NEXTSONG" for evdev, a CAN signal name, a GPIO line, ...)
+#[derive(Clone, Debug)]
+pub struct KeyBinding {
+    pub source: String,
+    pub keycode: KeyCode,
+    // Sent instead of `keycode` when the button is held for `InputServiceConfig::long_press_duration`
+    pub long_press: Option<KeyCode>,
+    pub metastate: u32,
+}
+
+impl KeyBinding {
+    pub fn new(source: &str, keycode: KeyCode) -> Self {
+        Self {
+            source: source.to_owned(),
+            keycode,
+            long_press: None,
+            metastate: 0,
+        }
+    }
+
+    pub fn with_long_press(mut self, keycode: KeyCode) -> Self {
+        self.long_press = Some(keycode);
+
+        self
+    }
+}
+
+// Detents arriving less than `interval` apart are multiplied, so spinning the
+// knob quickly scrolls further
+#[derive(Clone, Copy, Debug)]
+pub struct RotaryAcceleration {
+    pub interval: Duration,
+    pub multiplier: i32,
+    pub max_delta: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct RotaryBinding {
+    pub source: String,
+    pub keycode: KeyCode,
+    pub invert: bool,
+    pub acceleration: Option<RotaryAcceleration>,
+}
+
+impl RotaryBinding {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_owned(),
+            keycode: KeyCode::KeycodeRotaryController,
+            invert: false,
+            acceleration: None,
+        }
+    }
+
+    pub fn with_acceleration(mut self, acceleration: RotaryAcceleration) -> Self {
+        self.acceleration = Some(acceleration);
+
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct KeyMapConfig {
+    pub keys: Vec<KeyBinding>,
+    pub rotaries: Vec<RotaryBinding>,
+}
+
+impl KeyMapConfig {
+    // Every keycode the mapping can emit, to be advertised in `InputServiceConfig::keycodes`
+    pub fn keycodes(&self) -> Vec<KeyCode> {
+        let mut keycodes = vec![];
+
+        let key_keycodes = self.keys.iter().flat_map(|key| std::iter::once(key.keycode).chain(key.long_press));
+        let rotary_keycodes = self.rotaries.iter().map(|rotary| rotary.keycode);
+
+        for keycode in key_keycodes.chain(rotary_keycodes) {
+            if !keycodes.contains(&keycode) {
+                keycodes.push(keycode);
+            }
+        }
+
+        keycodes
+    }
+
+    // Parses a mapping like
+    //
+    // [[key]]
+    // source = "KEY_NEXTSONG"
+    // keycode = "MediaNext"
+    // long_press = "MediaFastForward"
+    //
+    // [[rotary]]
+    // source = "REL_DIAL"
+    // acceleration = { interval_ms = 40, multiplier = 3, max_delta = 10 }
+    #[cfg(feature = "toml")]
+    pub fn from_toml(data: &str) -> crate::error::Result<Self> {
+        let file: toml_config::KeyMapFile = toml::from_str(data)
+            .map_err(|e| crate::error::Error::Config(e.to_string()))?;
+
+        file.try_into()
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> crate::error::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+}
+
+// Accepts the protobuf name ("KeycodeMediaNext") or the name without prefix ("MediaNext")
+pub fn parse_keycode(name: &str) -> Option<KeyCode> {
+    let descriptor = KeyCode::enum_descriptor();
+
+    let value = descriptor.value_by_name(name)
+        .or_else(|| descriptor.value_by_name(&format!("Keycode{}", name)))?;
+
+    KeyCode::from_i32(value.value())
+}
+
+struct HeldKey {
+    pressed: Instant,
+    long_press_sent: bool,
+}
+
+// Translates physical button and knob events into InputReports according to a KeyMapConfig.
+// Call `tick` periodically (e.g. every 50 ms) so long presses fire while the button is still held,
+// otherwise they are only detected on release.
+pub struct KeyMap {
+    config: KeyMapConfig,
+    handle: InputHandle,
+    held: BTreeMap<String, HeldKey>,
+    last_rotary: BTreeMap<String, Instant>,
+}
+
+impl KeyMap {
+    pub fn new(config: KeyMapConfig, handle: InputHandle) -> Self {
+        Self {
+            config,
+            handle,
+            held: BTreeMap::new(),
+            last_rotary: BTreeMap::new(),
+        }
+    }
+
+    // Returns false if `source` isn't mapped
+    pub fn button(&mut self, source: &str, pressed: bool) -> bool {
+        self.button_at(source, pressed, Instant::now())
+    }
+
+    pub fn tick(&mut self) {
+        self.tick_at(Instant::now());
+    }
+
+    // `steps` are the detents the knob moved, positive is clockwise
+    pub fn rotary(&mut self, source: &str, steps: i32) -> bool {
+        self.rotary_at(source, steps, Instant::now())
+    }
+
+    fn button_at(&mut self, source: &str, pressed: bool, now: Instant) -> bool {
+        let Some(binding) = self.config.keys.iter().find(|key| key.source == source) else {
+            return false;
+        };
+
+        let Some(long_press) = binding.long_press else {
+            if pressed {
+                self.handle.key_down(binding.keycode, binding.metastate);
+            } else {
+                self.handle.key_up(binding.keycode, binding.metastate);
+            }
+
+            return true;
+        };
+
+        if pressed {
+            self.held.entry(source.to_owned()).or_insert(HeldKey {
+                pressed: now,
+                long_press_sent: false,
+            });
+
+            return true;
+        }
+
+        if let Some(held) = self.held.remove(source) {
+            if held.long_press_sent {
+                self.handle.key_up(long_press, binding.metastate);
+            } else if now.duration_since(held.pressed) >= self.handle.long_press_duration() {
+                self.handle.key_press(long_press, binding.metastate);
+            } else {
+                self.handle.key_press(binding.keycode, binding.metastate);
+            }
+        }
+
+        true
+    }
+
+    fn tick_at(&mut self, now: Instant) {
+        let long_press_duration = self.handle.long_press_duration();
+
+        for (source, held) in self.held.iter_mut() {
+            if held.long_press_sent || now.duration_since(held.pressed) < long_press_duration {
+                continue;
+            }
+
+            let binding = self.config.keys.iter().find(|key| &key.source == source);
+            if let Some(KeyBinding { long_press: Some(long_press), metastate, .. }) = binding {
+                self.handle.key_down(*long_press, *metastate);
+                held.long_press_sent = true;
+            }
+        }
+    }
+
+    fn rotary_at(&mut self, source: &str, steps: i32, now: Instant) -> bool {
+        let Some(binding) = self.config.rotaries.iter().find(|rotary| rotary.source == source) else {
+            return false;
+        };
+
+        if steps == 0 {
+            return true;
+        }
+
+        let last = self.last_rotary.insert(source.to_owned(), now);
+
+        let mut delta = if binding.invert { -steps } else { steps };
+
+        if let Some(acceleration) = binding.acceleration
+            && last.is_some_and(|last| now.duration_since(last) < acceleration.interval) {
+            let max_delta = acceleration.max_delta.max(1);
+            delta = delta.saturating_mul(acceleration.multiplier).clamp(-max_delta, max_delta);
+        }
+
+        self.handle.send_relative_event(binding.keycode, delta);
+
+        true
+    }
+}
+
+#[cfg(feature = "toml")]
+mod toml_config {
+    use super::{parse_keycode, KeyBinding, KeyMapConfig, RotaryAcceleration, RotaryBinding};
+    use crate::error::Error;
+    use crate::service::input::KeyCode;
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    pub(super) struct KeyMapFile {
+        #[serde(default)]
+        key: Vec<KeyEntry>,
+        #[serde(default)]
+        rotary: Vec<RotaryEntry>,
+    }
+
+    #[derive(Deserialize)]
+    struct KeyEntry {
+        source: String,
+        keycode: String,
+        long_press: Option<String>,
+        #[serde(default)]
+        metastate: u32,
+    }
+
+    #[derive(Deserialize)]
+    struct RotaryEntry {
+        source: String,
+        keycode: Option<String>,
+        #[serde(default)]
+        invert: bool,
+        acceleration: Option<AccelerationEntry>,
+    }
+
+    #[derive(Deserialize)]
+    struct AccelerationEntry {
+        interval_ms: u64,
+        multiplier: i32,
+        max_delta: i32,
+    }
+
+    fn keycode(name: &str) -> crate::error::Result<KeyCode> {
+        parse_keycode(name).ok_or_else(|| Error::Config(format!("unknown keycode {}", name)))
+    }
+
+    impl TryFrom<KeyMapFile> for KeyMapConfig {
+        type Error = Error;
+
+        fn try_from(file: KeyMapFile) -> crate::error::Result<Self> {
+            let mut keys = vec![];
+            for entry in file.key {
+                keys.push(KeyBinding {
+                    source: entry.source,
+                    keycode: keycode(&entry.keycode)?,
+                    long_press: entry.long_press.as_deref().map(keycode).transpose()?,
+                    metastate: entry.metastate,
+                });
+            }
+
+            let mut rotaries = vec![];
+            for entry in file.rotary {
+                rotaries.push(RotaryBinding {
+                    source: entry.source,
+                    keycode: match entry.keycode {
+                        Some(name) => keycode(&name)?,
+                        None => KeyCode::KeycodeRotaryController,
+                    },
+                    invert: entry.invert,
+                    acceleration: entry.acceleration.map(|acceleration| RotaryAcceleration {
+                        interval: Duration::from_millis(acceleration.interval_ms),
+                        multiplier: acceleration.multiplier,
+                        max_delta: acceleration.max_delta,
+                    }),
+                });
+            }
+
+            Ok(KeyMapConfig {
+                keys,
+                rotaries,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::input::testing::{input_handle, sent, Sent};
+
+    fn keys(sent: Vec<Sent>) -> Vec<(KeyCode, bool)> {
+        sent.into_iter().filter_map(|sent| match sent {
+            Sent::Key { keycode, down, .. } => Some((keycode, down)),
+            _ => None,
+        }).collect()
+    }
+
+    fn keymap(config: KeyMapConfig) -> (KeyMap, std::sync::Arc<crate::connection::ConnectionContext>) {
+        let (handle, context) = input_handle();
+
+        (KeyMap::new(config, handle), context)
+    }
+
+    fn long_press_config() -> KeyMapConfig {
+        KeyMapConfig {
+            keys: vec![
+                KeyBinding::new("KEY_NEXTSONG", KeyCode::KeycodeMediaNext).with_long_press(KeyCode::KeycodeMediaFastForward),
+                KeyBinding::new("KEY_ENTER", KeyCode::KeycodeDPadCenter),
+            ],
+            rotaries: vec![],
+        }
+    }
+
+    #[test]
+    fn unmapped_sources() {
+        let (mut keymap, context) = keymap(long_press_config());
+
+        assert!(!keymap.button("KEY_UNKNOWN", true));
+        assert!(!keymap.rotary("REL_DIAL", 1));
+        assert!(sent(&context).is_empty());
+    }
+
+    #[test]
+    fn plain_key_follows_the_button() {
+        let (mut keymap, context) = keymap(long_press_config());
+
+        assert!(keymap.button("KEY_ENTER", true));
+        assert_eq!(keys(sent(&context)), [(KeyCode::KeycodeDPadCenter, true)]);
+
+        assert!(keymap.button("KEY_ENTER", false));
+        assert_eq!(keys(sent(&context)), [(KeyCode::KeycodeDPadCenter, false)]);
+    }
+
+    #[test]
+    fn short_press() {
+        let (mut keymap, context) = keymap(long_press_config());
+        let start = Instant::now();
+
+        keymap.button_at("KEY_NEXTSONG", true, start);
+        keymap.tick_at(start + Duration::from_millis(499));
+        assert!(sent(&context).is_empty());
+
+        keymap.button_at("KEY_NEXTSONG", false, start + Duration::from_millis(499));
+        assert_eq!(keys(sent(&context)), [(KeyCode::KeycodeMediaNext, true), (KeyCode::KeycodeMediaNext, false)]);
+    }
+
+    #[test]
+    fn long_press_on_release() {
+        let (mut keymap, context) = keymap(long_press_config());
+        let start = Instant::now();
+
+        // Without a tick the long press is only noticed when the button is released
+        keymap.button_at("KEY_NEXTSONG", true, start);
+        keymap.button_at("KEY_NEXTSONG", false, start + Duration::from_millis(500));
+        assert_eq!(keys(sent(&context)), [(KeyCode::KeycodeMediaFastForward, true), (KeyCode::KeycodeMediaFastForward, false)]);
+    }
+
+    #[test]
+    fn long_press_on_tick() {
+        let (mut keymap, context) = keymap(long_press_config());
+        let start = Instant::now();
+
+        keymap.button_at("KEY_NEXTSONG", true, start);
+        // Repeated presses while held don't restart the timer
+        keymap.button_at("KEY_NEXTSONG", true, start + Duration::from_millis(300));
+        keymap.tick_at(start + Duration::from_millis(500));
+        assert_eq!(keys(sent(&context)), [(KeyCode::KeycodeMediaFastForward, true)]);
+
+        // Sent only once
+        keymap.tick_at(start + Duration::from_millis(1000));
+        assert!(sent(&context).is_empty());
+
+        keymap.button_at("KEY_NEXTSONG", false, start + Duration::from_millis(1200));
+        assert_eq!(keys(sent(&context)), [(KeyCode::KeycodeMediaFastForward, false)]);
+    }
+
+    #[test]
+    fn rotary() {
+        let mut binding = RotaryBinding::new("REL_DIAL");
+        binding.invert = true;
+        let (mut keymap, context) = keymap(KeyMapConfig { keys: vec![], rotaries: vec![binding] });
+
+        assert!(keymap.rotary("REL_DIAL", 2));
+        assert!(keymap.rotary("REL_DIAL", 0));
+        assert!(keymap.rotary("REL_DIAL", -1));
+        assert_eq!(sent(&context), [Sent::Relative(KeyCode::KeycodeRotaryController, -2), Sent::Relative(KeyCode::KeycodeRotaryController, 1)]);
+    }
+
+    #[test]
+    fn rotary_acceleration() {
+        let binding = RotaryBinding::new("REL_DIAL").with_acceleration(RotaryAcceleration {
+            interval: Duration::from_millis(40),
+            multiplier: 3,
+            max_delta: 10,
+        });
+        let (mut keymap, context) = keymap(KeyMapConfig { keys: vec![], rotaries: vec![binding] });
+        let start = Instant::now();
+
+        // The first detent isn't accelerated, the following fast ones are
+        keymap.rotary_at("REL_DIAL", 1, start);
+        keymap.rotary_at("REL_DIAL", 1, start + Duration::from_millis(20));
+        keymap.rotary_at("REL_DIAL", -2, start + Duration::from_millis(40));
+        // Clamped to `max_delta`
+        keymap.rotary_at("REL_DIAL", 5, start + Duration::from_millis(50));
+        // Slow again
+        keymap.rotary_at("REL_DIAL", 1, start + Duration::from_millis(90));
+
+        let deltas: Vec<i32> = sent(&context).into_iter().map(|sent| match sent {
+            Sent::Relative(_, delta) => delta,
+            sent => panic!("unexpected {:?}", sent),
+        }).collect();
+        assert_eq!(deltas, [1, 3, -6, 10, 1]);
+    }
+
+    #[test]
+    fn keycodes() {
+        let mut config = long_press_config();
+        config.keys.push(KeyBinding::new("KEY_PLAYPAUSE", KeyCode::KeycodeMediaNext));
+        config.rotaries.push(RotaryBinding::new("REL_DIAL"));
+
+        assert_eq!(config.keycodes(), [
+            KeyCode::KeycodeMediaNext,
+            KeyCode::KeycodeMediaFastForward,
+            KeyCode::KeycodeDPadCenter,
+            KeyCode::KeycodeRotaryController,
+        ]);
+    }
+
+    #[test]
+    fn keycode_names() {
+        assert_eq!(parse_keycode("MediaNext"), Some(KeyCode::KeycodeMediaNext));
+        assert_eq!(parse_keycode("KeycodeMediaNext"), Some(KeyCode::KeycodeMediaNext));
+        assert_eq!(parse_keycode("media_next"), None);
+        assert_eq!(parse_keycode(""), None);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml() {
+        let config = KeyMapConfig::from_toml(r#"
+            [[key]]
+            source = "KEY_NEXTSONG"
+            keycode = "MediaNext"
+            long_press = "KeycodeMediaFastForward"
+
+            [[key]]
+            source = "KEY_ENTER"
+            keycode = "DPadCenter"
+            metastate = 1
+
+            [[rotary]]
+            source = "REL_DIAL"
+            invert = true
+            acceleration = { interval_ms = 40, multiplier = 3, max_delta = 10 }
+
+            [[rotary]]
+            source = "REL_HWHEEL"
+            keycode = "MediaNext"
+        "#).unwrap();
+
+        assert_eq!(config.keys.len(), 2);
+        assert_eq!(config.keys[0].source, "KEY_NEXTSONG");
+        assert_eq!(config.keys[0].keycode, KeyCode::KeycodeMediaNext);
+        assert_eq!(config.keys[0].long_press, Some(KeyCode::KeycodeMediaFastForward));
+        assert_eq!(config.keys[0].metastate, 0);
+        assert_eq!(config.keys[1].long_press, None);
+        assert_eq!(config.keys[1].metastate, 1);
+
+        assert_eq!(config.rotaries.len(), 2);
+        assert_eq!(config.rotaries[0].keycode, KeyCode::KeycodeRotaryController);
+        assert!(config.rotaries[0].invert);
+        let acceleration = config.rotaries[0].acceleration.unwrap();
+        assert_eq!((acceleration.interval, acceleration.multiplier, acceleration.max_delta), (Duration::from_millis(40), 3, 10));
+        assert_eq!(config.rotaries[1].keycode, KeyCode::KeycodeMediaNext);
+        assert!(!config.rotaries[1].invert);
+        assert!(config.rotaries[1].acceleration.is_none());
+
+        assert!(KeyMapConfig::from_toml("").unwrap().keys.is_empty());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_errors() {
+        let error = KeyMapConfig::from_toml(r#"
+            [[key]]
+            source = "KEY_NEXTSONG"
+            keycode = "NoSuchKey"
+        "#).unwrap_err();
+        assert!(matches!(&error, crate::error::Error::Config(message) if message == "unknown keycode NoSuchKey"), "{:?}", error);
+
+        let error = KeyMapConfig::from_toml(r#"
+            [[rotary]]
+            source = "REL_DIAL"
+            keycode = "NoSuchKnob"
+        "#).unwrap_err();
+        assert!(matches!(&error, crate::error::Error::Config(message) if message == "unknown keycode NoSuchKnob"), "{:?}", error);
+
+        // Missing keycode
+        assert!(matches!(KeyMapConfig::from_toml("[[key]]\nsource = \"KEY_A\""), Err(crate::error::Error::Config(_))));
+    }
+}
@@ -0,0 +1,4 @@
+#[cfg(feature = "evdev")]
+pub mod evdev;
+pub mod keymap;
+pub mod multitouch;
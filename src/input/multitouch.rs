@@ -0,0 +1,199 @@
+use crate::service::input::{InputHandle, PointerAction, TouchPointer, TouchSurface};
+use std::collections::BTreeMap;
+
+struct Contact {
+    pointer_id: u32,
+    x: u32,
+    y: u32,
+    moved: bool,
+    released: bool,
+}
+
+// Turns raw per-finger contact updates of a touch controller into Android style touch events.
+// Contacts are reported with `update`/`release` and are sent to the phone on `sync`, which
+// should be called once per controller frame (e.g. on SYN_REPORT).
+pub struct MultiTouchTracker {
+    handle: InputHandle,
+    surface: TouchSurface,
+    // Pointers the phone knows about, in the order they went down
+    pointers: Vec<u32>,
+    contacts: BTreeMap<u32, Contact>,
+}
+
+impl MultiTouchTracker {
+    pub fn new(handle: InputHandle) -> Self {
+        Self::for_surface(handle, TouchSurface::Touchscreen)
+    }
+
+    pub fn for_surface(handle: InputHandle, surface: TouchSurface) -> Self {
+        Self {
+            handle,
+            surface,
+            pointers: vec![],
+            contacts: BTreeMap::new(),
+        }
+    }
+
+    // `contact_id` is whatever the controller uses to identify a finger (slot, tracking id, ...)
+    pub fn update(&mut self, contact_id: u32, x: u32, y: u32) {
+        if let Some(contact) = self.contacts.get_mut(&contact_id) {
+            if contact.x != x || contact.y != y {
+                contact.x = x;
+                contact.y = y;
+                contact.moved = true;
+            }
+            contact.released = false;
+
+            return;
+        }
+
+        let pointer_id = self.free_pointer_id();
+        self.contacts.insert(contact_id, Contact { pointer_id, x, y, moved: false, released: false });
+    }
+
+    pub fn release(&mut self, contact_id: u32) {
+        if let Some(contact) = self.contacts.get_mut(&contact_id) {
+            contact.released = true;
+        }
+    }
+
+    pub fn sync(&mut self) {
+        // Moves of pointers the phone already knows
+        let moved = self.contacts.values().any(|contact| contact.moved && self.pointers.contains(&contact.pointer_id));
+        if moved {
+            self.send(PointerAction::TouchActionMove, 0);
+        }
+        for contact in self.contacts.values_mut() {
+            contact.moved = false;
+        }
+
+        // New fingers, before the lifted ones so a tap within one frame is still a down and up
+        let new: Vec<u32> = self.contacts.values()
+            .map(|contact| contact.pointer_id)
+            .filter(|pointer_id| !self.pointers.contains(pointer_id))
+            .collect();
+
+        for pointer_id in new {
+            self.pointers.push(pointer_id);
+
+            let action = if self.pointers.len() == 1 { PointerAction::TouchActionDown } else { PointerAction::TouchActionPointerDown };
+            self.send(action, (self.pointers.len() - 1) as u32);
+        }
+
+        // Lifted fingers
+        let released: Vec<u32> = self.contacts.iter()
+            .filter(|(_, contact)| contact.released)
+            .map(|(contact_id, _)| *contact_id)
+            .collect();
+
+        for contact_id in released {
+            let contact = self.contacts.remove(&contact_id).unwrap();
+
+            if let Some(index) = self.pointers.iter().position(|id| *id == contact.pointer_id) {
+                let action = if self.pointers.len() == 1 { PointerAction::TouchActionUp } else { PointerAction::TouchActionPointerUp };
+                self.send_with(action, index as u32, Some(&contact));
+
+                self.pointers.remove(index);
+            }
+        }
+    }
+
+    // Aborts the current gesture, e.g. when the touch device disappears
+    pub fn cancel(&mut self) {
+        if !self.pointers.is_empty() {
+            self.send(PointerAction::TouchActionCancel, 0);
+        }
+
+        self.pointers.clear();
+        self.contacts.clear();
+    }
+
+    fn send(&self, action: PointerAction, action_index: u32) {
+        self.send_with(action, action_index, None);
+    }
+
+    // `removed` is a contact that is already gone from `contacts` but still has to be reported
+    fn send_with(&self, action: PointerAction, action_index: u32, removed: Option<&Contact>) {
+        let pointers: Vec<TouchPointer> = self.pointers.iter()
+            .filter_map(|pointer_id| {
+                self.contacts.values()
+                    .chain(removed)
+                    .find(|contact| contact.pointer_id == *pointer_id)
+                    .map(|contact| TouchPointer { id: contact.pointer_id, x: contact.x, y: contact.y })
+            })
+            .collect();
+
+        self.handle.send_surface_event(self.surface, action, action_index, &pointers);
+    }
+
+    // Android expects small pointer ids, reuse the lowest free one
+    fn free_pointer_id(&self) -> u32 {
+        (0..).find(|id| !self.contacts.values().any(|contact| contact.pointer_id == *id)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionContext;
+    use crate::service::input::testing::{input_handle, sent, Sent};
+    use std::sync::Arc;
+
+    fn tracker() -> (MultiTouchTracker, Arc<ConnectionContext>) {
+        let (handle, context) = input_handle();
+
+        (MultiTouchTracker::for_surface(handle, TouchSurface::Touchpad), context)
+    }
+
+    #[test]
+    fn tap_within_one_frame() {
+        let (mut tracker, context) = tracker();
+
+        tracker.update(7, 10, 20);
+        tracker.release(7);
+        tracker.sync();
+
+        assert_eq!(sent(&context), vec![
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 10, 20)]),
+            Sent::Touchpad(PointerAction::TouchActionUp, 0, vec![(0, 10, 20)]),
+        ]);
+    }
+
+    #[test]
+    fn tap_over_two_frames() {
+        let (mut tracker, context) = tracker();
+
+        tracker.update(7, 10, 20);
+        tracker.sync();
+        tracker.release(7);
+        tracker.sync();
+
+        assert_eq!(sent(&context), vec![
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 10, 20)]),
+            Sent::Touchpad(PointerAction::TouchActionUp, 0, vec![(0, 10, 20)]),
+        ]);
+    }
+
+    #[test]
+    fn second_finger() {
+        let (mut tracker, context) = tracker();
+
+        tracker.update(3, 10, 20);
+        tracker.sync();
+        tracker.update(5, 30, 40);
+        tracker.update(3, 11, 20);
+        tracker.sync();
+        tracker.release(3);
+        tracker.sync();
+        tracker.release(5);
+        tracker.sync();
+
+        assert_eq!(sent(&context), vec![
+            Sent::Touchpad(PointerAction::TouchActionDown, 0, vec![(0, 10, 20)]),
+            Sent::Touchpad(PointerAction::TouchActionMove, 0, vec![(0, 11, 20)]),
+            Sent::Touchpad(PointerAction::TouchActionPointerDown, 1, vec![(0, 11, 20), (1, 30, 40)]),
+            Sent::Touchpad(PointerAction::TouchActionPointerUp, 0, vec![(0, 11, 20), (1, 30, 40)]),
+            Sent::Touchpad(PointerAction::TouchActionUp, 0, vec![(1, 30, 40)]),
+        ]);
+    }
+}
@@ -7,6 +7,11 @@ pub mod message;
 pub mod service;
 pub mod frame;
 pub mod channel;
+pub mod recorder;
+pub mod codec;
+pub mod input;
+pub mod media;
+pub mod sensor;
 
 mod protobuf {
     include!(concat!(env!("OUT_DIR"), "/protobuf/mod.rs"));
@@ -0,0 +1,302 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Gif,
+    Bmp,
+    Unknown,
+}
+
+impl ImageFormat {
+    // Detects the format from the magic bytes
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            ImageFormat::Png
+        } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            ImageFormat::Jpeg
+        } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            ImageFormat::Webp
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            ImageFormat::Gif
+        } else if data.starts_with(b"BM") {
+            ImageFormat::Bmp
+        } else {
+            ImageFormat::Unknown
+        }
+    }
+
+    pub fn mime_type(&self) -> Option<&'static str> {
+        match self {
+            ImageFormat::Png => Some("image/png"),
+            ImageFormat::Jpeg => Some("image/jpeg"),
+            ImageFormat::Webp => Some("image/webp"),
+            ImageFormat::Gif => Some("image/gif"),
+            ImageFormat::Bmp => Some("image/bmp"),
+            ImageFormat::Unknown => None,
+        }
+    }
+
+    // Formats the `image` feature can decode, GIF art is only kept encoded
+    pub fn is_decodable(&self) -> bool {
+        matches!(self, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Webp | ImageFormat::Bmp)
+    }
+}
+
+// 8 bit RGBA pixels, row by row
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AlbumArtConfig {
+    // Decode the art (requires the `image` feature), otherwise only the encoded bytes are kept.
+    // See `ImageFormat::is_decodable` for the supported formats.
+    pub decode: bool,
+    // Scale decoded art to fit into width x height, keeping the aspect ratio
+    pub size: Option<(u32, u32)>,
+    // How many covers are kept around, e.g. to switch back and forth between tracks
+    pub capacity: usize,
+}
+
+impl Default for AlbumArtConfig {
+    fn default() -> Self {
+        Self {
+            decode: cfg!(feature = "image"),
+            size: None,
+            capacity: 8,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AlbumArt {
+    // Hash of the encoded bytes, identical covers have the same hash
+    pub hash: u64,
+    pub format: ImageFormat,
+    pub data: Vec<u8>,
+    pub decoded: Option<DecodedImage>,
+}
+
+impl AlbumArt {
+    pub fn hash_of(data: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    fn new(config: &AlbumArtConfig, data: &[u8]) -> Self {
+        let format = ImageFormat::detect(data);
+
+        let decoded = if config.decode && format.is_decodable() {
+            decode(data, config.size)
+        } else {
+            None
+        };
+
+        Self {
+            hash: Self::hash_of(data),
+            format,
+            data: data.to_vec(),
+            decoded,
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+fn decode(data: &[u8], size: Option<(u32, u32)>) -> Option<DecodedImage> {
+    let image = match image::load_from_memory(data) {
+        Ok(image) => image,
+        Err(e) => {
+            println!("AlbumArt: Failed to decode: {}", e);
+            return None;
+        }
+    };
+
+    let image = match size {
+        Some((width, height)) if image.width() > width || image.height() > height => {
+            image.resize(width, height, image::imageops::FilterType::Triangle)
+        }
+        _ => image,
+    };
+
+    let image = image.to_rgba8();
+
+    Some(DecodedImage {
+        width: image.width(),
+        height: image.height(),
+        rgba: image.into_raw(),
+    })
+}
+
+#[cfg(not(feature = "image"))]
+fn decode(_data: &[u8], _size: Option<(u32, u32)>) -> Option<DecodedImage> {
+    None
+}
+
+// Keeps recently seen covers by hash so metadata updates carrying the same
+// art don't get decoded again
+pub struct AlbumArtCache {
+    config: AlbumArtConfig,
+    // Most recently used last
+    entries: Vec<Arc<AlbumArt>>,
+}
+
+impl AlbumArtCache {
+    pub fn new(config: AlbumArtConfig) -> Self {
+        Self {
+            config,
+            entries: vec![],
+        }
+    }
+
+    pub fn get(&self, hash: u64) -> Option<Arc<AlbumArt>> {
+        self.entries.iter().find(|art| art.hash == hash).cloned()
+    }
+
+    // Returns the cached art for these bytes, decoding them only when they weren't seen before
+    pub fn insert(&mut self, data: &[u8]) -> Arc<AlbumArt> {
+        let hash = AlbumArt::hash_of(data);
+
+        let art = match self.entries.iter().position(|art| art.hash == hash && art.data == data) {
+            Some(index) => self.entries.remove(index),
+            None => Arc::new(AlbumArt::new(&self.config, data)),
+        };
+
+        self.entries.push(Arc::clone(&art));
+
+        if self.entries.len() > self.config.capacity.max(1) {
+            self.entries.remove(0);
+        }
+
+        art
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\x00\x00\x00;";
+
+    fn config(capacity: usize) -> AlbumArtConfig {
+        AlbumArtConfig { decode: false, size: None, capacity }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(ImageFormat::detect(b"\x89PNG\r\n\x1a\n\x00"), ImageFormat::Png);
+        assert_eq!(ImageFormat::detect(&[0xFF, 0xD8, 0xFF, 0xE0]), ImageFormat::Jpeg);
+        assert_eq!(ImageFormat::detect(b"RIFF\x00\x00\x00\x00WEBPVP8 "), ImageFormat::Webp);
+        assert_eq!(ImageFormat::detect(b"GIF87a"), ImageFormat::Gif);
+        assert_eq!(ImageFormat::detect(GIF), ImageFormat::Gif);
+        assert_eq!(ImageFormat::detect(b"BM\x00\x00"), ImageFormat::Bmp);
+
+        // Truncated or unrelated data
+        assert_eq!(ImageFormat::detect(b"\x89PNG"), ImageFormat::Unknown);
+        assert_eq!(ImageFormat::detect(b"RIFF\x00\x00\x00\x00WAVE"), ImageFormat::Unknown);
+        assert_eq!(ImageFormat::detect(b"RIFF"), ImageFormat::Unknown);
+        assert_eq!(ImageFormat::detect(b"GIF"), ImageFormat::Unknown);
+        assert_eq!(ImageFormat::detect(&[]), ImageFormat::Unknown);
+
+        assert_eq!(ImageFormat::Jpeg.mime_type(), Some("image/jpeg"));
+        assert_eq!(ImageFormat::Unknown.mime_type(), None);
+        assert!(!ImageFormat::Gif.is_decodable());
+        assert!(!ImageFormat::Unknown.is_decodable());
+    }
+
+    #[test]
+    fn identical_art_is_shared() {
+        let mut cache = AlbumArtCache::new(config(8));
+
+        let first = cache.insert(b"cover");
+        // Same bytes in another buffer
+        let copy = b"cover".to_vec();
+        let second = cache.insert(&copy);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.hash, AlbumArt::hash_of(b"cover"));
+        assert_eq!(first.format, ImageFormat::Unknown);
+
+        let other = cache.insert(b"other cover");
+        assert!(!Arc::ptr_eq(&first, &other));
+        assert_ne!(first.hash, other.hash);
+
+        assert!(Arc::ptr_eq(&cache.get(first.hash).unwrap(), &first));
+        assert!(cache.get(0).is_none());
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let mut cache = AlbumArtCache::new(config(2));
+
+        let a = cache.insert(b"a");
+        let b = cache.insert(b"b");
+        // Using `a` again makes `b` the oldest
+        cache.insert(b"a");
+        let c = cache.insert(b"c");
+
+        assert!(cache.get(a.hash).is_some());
+        assert!(cache.get(b.hash).is_none());
+        assert!(cache.get(c.hash).is_some());
+
+        cache.clear();
+        assert!(cache.get(a.hash).is_none());
+    }
+
+    #[test]
+    fn keeps_at_least_one() {
+        let mut cache = AlbumArtCache::new(config(0));
+
+        let a = cache.insert(b"a");
+        assert!(cache.get(a.hash).is_some());
+
+        let b = cache.insert(b"b");
+        assert!(cache.get(a.hash).is_none());
+        assert!(cache.get(b.hash).is_some());
+    }
+
+    #[test]
+    fn gif_stays_encoded() {
+        let mut cache = AlbumArtCache::new(AlbumArtConfig { decode: true, ..Default::default() });
+
+        let art = cache.insert(GIF);
+        assert_eq!(art.format, ImageFormat::Gif);
+        assert_eq!(art.data, GIF);
+        assert!(art.decoded.is_none());
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn decode_and_scale() {
+        let mut png = std::io::Cursor::new(vec![]);
+        image::RgbaImage::from_pixel(4, 2, image::Rgba([255, 0, 0, 255]))
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+
+        let mut cache = AlbumArtCache::new(AlbumArtConfig { decode: true, size: Some((2, 2)), capacity: 8 });
+        let art = cache.insert(&png);
+        assert_eq!(art.format, ImageFormat::Png);
+
+        let decoded = art.decoded.as_ref().unwrap();
+        assert_eq!((decoded.width, decoded.height), (2, 1));
+        assert_eq!(decoded.rgba, [255, 0, 0, 255, 255, 0, 0, 255]);
+
+        // Broken data with a known signature isn't decoded
+        let art = cache.insert(&png[..20]);
+        assert_eq!(art.format, ImageFormat::Png);
+        assert!(art.decoded.is_none());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod album_art;
+#[cfg(feature = "mpris")]
+pub mod mpris;
@@ -0,0 +1,565 @@
+use crate::service::input::{InputHandle, KeyCode};
+use crate::service::media_play_back::{MediaPlaybackEvent, MediaPlaybackHandle, NowPlaying, PlaybackState};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+
+pub use zbus;
+
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+// A reported position further off than this from the extrapolated one counts as a seek
+const SEEK_THRESHOLD: Duration = Duration::from_secs(2);
+
+// The keycodes the player sends, they have to be part of `InputServiceConfig::keycodes`
+pub const MPRIS_KEYCODES: [KeyCode; 6] = [
+    KeyCode::KeycodeMediaPlay,
+    KeyCode::KeycodeMediaPause,
+    KeyCode::KeycodeMediaPlayPause,
+    KeyCode::KeycodeMediaStop,
+    KeyCode::KeycodeMediaNext,
+    KeyCode::KeycodeMediaPrevious,
+];
+
+#[derive(Clone, Debug)]
+pub struct MprisConfig {
+    // Registered as org.mpris.MediaPlayer2.<bus_name>
+    pub bus_name: String,
+    pub identity: String,
+    // D-Bus address to connect to instead of the session bus, e.g. a private dbus-daemon
+    pub address: Option<String>,
+    // Album art is written here so it can be published as mpris:artUrl
+    pub art_dir: PathBuf,
+}
+
+impl Default for MprisConfig {
+    fn default() -> Self {
+        Self {
+            bus_name: "anauuno".to_owned(),
+            identity: "Android Auto".to_owned(),
+            address: None,
+            art_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+struct Root {
+    identity: String,
+}
+
+#[zbus::interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        self.identity.clone()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec![]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+struct Player {
+    input: InputHandle,
+    art_dir: PathBuf,
+    bus_name: String,
+    // The published album art file, named after the art's hash since clients cache by URL
+    art_path: Option<PathBuf>,
+    // Counts metadata changes for mpris:trackid
+    track: u64,
+    // What the properties currently report
+    now_playing: NowPlaying,
+    // Last reported position and when it was received
+    position_at: Option<(Duration, Instant)>,
+}
+
+impl Player {
+    fn press(&self, keycode: KeyCode) {
+        self.input.key_press(keycode, 0);
+    }
+
+    fn current_position(&self) -> Duration {
+        match self.position_at {
+            Some((position, at)) if self.now_playing.is_playing() => position + at.elapsed(),
+            Some((position, _)) => position,
+            None => Duration::ZERO,
+        }
+    }
+
+    fn track_id(&self) -> String {
+        format!("/org/anauuno/track/{}", self.track)
+    }
+
+    fn art_path_for(&self, hash: u64) -> PathBuf {
+        self.art_dir.join(format!("anauuno-{}-{}-art-{:016x}", self.bus_name, std::process::id(), hash))
+    }
+
+    // Replaces the published art file when the cover changed
+    fn update_art(&mut self) -> std::io::Result<()> {
+        let album_art = self.now_playing.album_art.clone();
+        let art_path = album_art.as_ref().map(|art| self.art_path_for(art.hash));
+
+        if art_path == self.art_path {
+            return Ok(());
+        }
+
+        if let Some(old_path) = self.art_path.take() {
+            let _ = std::fs::remove_file(old_path);
+        }
+
+        if let (Some(art), Some(art_path)) = (album_art, art_path) {
+            if let Err(e) = std::fs::write(&art_path, &art.data) {
+                let _ = std::fs::remove_file(&art_path);
+                return Err(e);
+            }
+
+            self.art_path = Some(art_path);
+        }
+
+        Ok(())
+    }
+
+    // Emits PropertiesChanged (and Seeked) for what differs from `old`
+    fn emit_changes(&mut self, emitter: &SignalEmitter<'_>, old: &NowPlaying) -> zbus::Result<()> {
+        let now_playing = self.now_playing.clone();
+
+        if now_playing.metadata != old.metadata {
+            self.track += 1;
+
+            // Without the file the metadata is published without mpris:artUrl
+            if let Err(e) = self.update_art() {
+                println!("MprisServer: Failed to write album art to {}: {}", self.art_dir.display(), e);
+            }
+
+            zbus::block_on(self.metadata_changed(emitter))?;
+        }
+
+        if now_playing.status.state != old.status.state {
+            zbus::block_on(self.playback_status_changed(emitter))?;
+        }
+
+        if now_playing.status.shuffle != old.status.shuffle {
+            zbus::block_on(self.shuffle_changed(emitter))?;
+        }
+
+        if (now_playing.status.repeat, now_playing.status.repeat_one) != (old.status.repeat, old.status.repeat_one) {
+            zbus::block_on(self.loop_status_changed(emitter))?;
+        }
+
+        if now_playing.status.position != old.status.position || now_playing.status.state != old.status.state {
+            let expected = self.position_at.map(|_| self.current_position());
+
+            self.position_at = now_playing.status.position.map(|position| (position, Instant::now()));
+
+            let position = self.current_position();
+            if let Some(expected) = expected && position.abs_diff(expected) > SEEK_THRESHOLD {
+                zbus::block_on(Player::seeked(emitter, position.as_micros() as i64))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) {
+        self.press(KeyCode::KeycodeMediaNext);
+    }
+
+    fn previous(&self) {
+        self.press(KeyCode::KeycodeMediaPrevious);
+    }
+
+    fn pause(&self) {
+        self.press(KeyCode::KeycodeMediaPause);
+    }
+
+    fn play_pause(&self) {
+        self.press(KeyCode::KeycodeMediaPlayPause);
+    }
+
+    fn stop(&self) {
+        self.press(KeyCode::KeycodeMediaStop);
+    }
+
+    fn play(&self) {
+        self.press(KeyCode::KeycodeMediaPlay);
+    }
+
+    // CanSeek is false, the phone can't be seeked over the protocol
+    fn seek(&self, _offset: i64) {}
+
+    fn set_position(&self, _track_id: ObjectPath<'_>, _position: i64) {}
+
+    fn open_uri(&self, _uri: &str) -> zbus::fdo::Result<()> {
+        Err(zbus::fdo::Error::NotSupported("OpenUri is not supported".to_owned()))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        match self.now_playing.status.state {
+            Some(PlaybackState::MediaServiceStatePlaying) => "Playing",
+            Some(PlaybackState::MediaServiceStatePause) => "Paused",
+            _ => "Stopped",
+        }.to_owned()
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> String {
+        let status = &self.now_playing.status;
+
+        if status.repeat_one == Some(true) {
+            "Track"
+        } else if status.repeat == Some(true) {
+            "Playlist"
+        } else {
+            "None"
+        }.to_owned()
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        self.now_playing.status.shuffle.unwrap_or(false)
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let metadata = &self.now_playing.metadata;
+        let mut map = HashMap::new();
+
+        let mut insert = |key: &str, value: Value<'_>| {
+            map.insert(key.to_owned(), value.try_into().unwrap());
+        };
+
+        insert("mpris:trackid", Value::from(ObjectPath::try_from(self.track_id()).unwrap()));
+
+        if let Some(song) = &metadata.song {
+            insert("xesam:title", Value::from(song.as_str()));
+        }
+        if let Some(artist) = &metadata.artist {
+            insert("xesam:artist", Value::from(vec![artist.as_str()]));
+        }
+        if let Some(album) = &metadata.album {
+            insert("xesam:album", Value::from(album.as_str()));
+        }
+        if let Some(duration) = metadata.duration {
+            insert("mpris:length", Value::from(duration.as_micros() as i64));
+        }
+        if let Some(rating) = metadata.rating {
+            // The phone rates 0 to 5 stars
+            insert("xesam:userRating", Value::from((rating as f64 / 5.0).clamp(0.0, 1.0)));
+        }
+        if let Some(art_path) = &self.art_path {
+            insert("mpris:artUrl", Value::from(format!("file://{}", art_path.display())));
+        }
+
+        map
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.current_position().as_micros() as i64
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+// Publishes the phone's media session as an MPRIS player, Play/Pause/Next/... are sent
+// to the phone as media keys through `InputHandle`
+pub struct MprisServer {
+    playback: MediaPlaybackHandle,
+    connection: zbus::blocking::Connection,
+}
+
+impl MprisServer {
+    pub fn new(config: MprisConfig, playback: MediaPlaybackHandle, input: InputHandle) -> crate::error::Result<Self> {
+        let builder = match &config.address {
+            Some(address) => zbus::blocking::connection::Builder::address(address.as_str())?,
+            None => zbus::blocking::connection::Builder::session()?,
+        };
+
+        let player = Player {
+            input,
+            art_dir: config.art_dir,
+            bus_name: config.bus_name.clone(),
+            art_path: None,
+            track: 0,
+            now_playing: NowPlaying::default(),
+            position_at: None,
+        };
+
+        let connection = builder
+            .name(format!("org.mpris.MediaPlayer2.{}", config.bus_name))?
+            .serve_at(OBJECT_PATH, Root { identity: config.identity })?
+            .serve_at(OBJECT_PATH, player)?
+            .build()?;
+
+        println!("MprisServer: Registered org.mpris.MediaPlayer2.{}", config.bus_name);
+
+        let server = Self { playback, connection };
+        server.refresh()?;
+
+        Ok(server)
+    }
+
+    // Picks up the latest now playing snapshot and emits PropertiesChanged for whatever changed,
+    // call it for every `MediaPlaybackEvent`
+    pub fn refresh(&self) -> crate::error::Result<()> {
+        let now_playing = self.playback.now_playing();
+
+        let player = self.connection.object_server().interface::<_, Player>(OBJECT_PATH)?;
+        let emitter = player.signal_emitter();
+        let mut player = player.get_mut();
+
+        // The properties already report the new state while the signals are emitted, it is
+        // rolled back on failure so the next refresh emits them again
+        let old = std::mem::replace(&mut player.now_playing, now_playing);
+        let (old_track, old_position_at) = (player.track, player.position_at);
+
+        if let Err(e) = player.emit_changes(emitter, &old) {
+            player.now_playing = old;
+            player.track = old_track;
+            player.position_at = old_position_at;
+
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    // Refreshes on every event until the sender is dropped
+    pub fn run(&self, events: Receiver<MediaPlaybackEvent>) -> crate::error::Result<()> {
+        for _ in events {
+            self.refresh()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for MprisServer {
+    fn drop(&mut self) {
+        if let Ok(player) = self.connection.object_server().interface::<_, Player>(OBJECT_PATH)
+            && let Some(art_path) = &player.get().art_path {
+            let _ = std::fs::remove_file(art_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionContext;
+    use crate::message::{MediaPlaybackMessageType, Message};
+    use crate::protobuf::playback::MediaMetaData;
+    use crate::service::input::testing::{input_handle, sent, Sent};
+    use crate::service::media_play_back::MediaPlayBackService;
+    use crate::service::Service;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::Arc;
+    use zbus::blocking::fdo::PropertiesProxy;
+    use zbus::names::InterfaceName;
+
+    // A private session bus, killed on drop
+    struct DBusDaemon {
+        child: Child,
+        address: String,
+    }
+
+    impl DBusDaemon {
+        fn start() -> Self {
+            let mut child = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon not found");
+
+            let mut address = String::new();
+            BufReader::new(child.stdout.as_mut().unwrap()).read_line(&mut address).unwrap();
+
+            Self { child, address: address.trim().to_owned() }
+        }
+    }
+
+    impl Drop for DBusDaemon {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    fn metadata(service: &mut MediaPlayBackService, song: &str, album_art: &[u8]) {
+        let mut data = MediaMetaData::new();
+        data.song = Some(song.to_owned());
+        data.album_art = Some(album_art.to_vec());
+
+        service.handle_message(Message::new_with_protobuf_message(1, false, data, MediaPlaybackMessageType::PlaybackMetadata as u16));
+    }
+
+    fn player_metadata(client: &zbus::blocking::Connection, bus_name: &str) -> HashMap<String, OwnedValue> {
+        let properties = PropertiesProxy::builder(client)
+            .destination(format!("org.mpris.MediaPlayer2.{}", bus_name)).unwrap()
+            .path(OBJECT_PATH).unwrap()
+            .build().unwrap();
+
+        let interface = InterfaceName::try_from("org.mpris.MediaPlayer2.Player").unwrap();
+        let metadata = properties.get(interface, "Metadata").unwrap();
+
+        HashMap::<String, OwnedValue>::try_from(metadata).unwrap()
+    }
+
+    fn string(metadata: &HashMap<String, OwnedValue>, key: &str) -> Option<String> {
+        metadata.get(key).map(|value| String::try_from(value.try_clone().unwrap()).unwrap())
+    }
+
+    fn setup(daemon: &DBusDaemon, bus_name: &str, art_dir: PathBuf) -> (MprisServer, MediaPlayBackService, Arc<ConnectionContext>) {
+        let (input, context) = input_handle();
+        let playback = MediaPlayBackService::new(Arc::new(ConnectionContext::new()));
+
+        let config = MprisConfig {
+            bus_name: bus_name.to_owned(),
+            address: Some(daemon.address.clone()),
+            art_dir,
+            ..Default::default()
+        };
+
+        (MprisServer::new(config, playback.handle(), input).unwrap(), playback, context)
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn private_bus() {
+        let daemon = DBusDaemon::start();
+        let art_dir = std::env::temp_dir().join(format!("anauuno-mpris-test-{}", std::process::id()));
+        std::fs::create_dir_all(&art_dir).unwrap();
+
+        let (server, mut playback, context) = setup(&daemon, "test", art_dir.clone());
+        let client = zbus::blocking::connection::Builder::address(daemon.address.as_str()).unwrap().build().unwrap();
+
+        metadata(&mut playback, "First", b"\x89PNG\r\n\x1a\nfirst");
+        server.refresh().unwrap();
+
+        let first = player_metadata(&client, "test");
+        assert_eq!(string(&first, "xesam:title").as_deref(), Some("First"));
+        let first_url = string(&first, "mpris:artUrl").unwrap();
+        let first_path = PathBuf::from(first_url.strip_prefix("file://").unwrap());
+        assert_eq!(std::fs::read(&first_path).unwrap(), b"\x89PNG\r\n\x1a\nfirst");
+
+        metadata(&mut playback, "Second", b"\x89PNG\r\n\x1a\nsecond");
+        server.refresh().unwrap();
+
+        // A new cover gets a new URL and the old file is gone
+        let second_url = string(&player_metadata(&client, "test"), "mpris:artUrl").unwrap();
+        assert_ne!(first_url, second_url);
+        assert!(!first_path.exists());
+
+        // Player methods become media keys
+        client.call_method(Some("org.mpris.MediaPlayer2.test"), OBJECT_PATH, Some("org.mpris.MediaPlayer2.Player"), "Next", &()).unwrap();
+        assert_eq!(sent(&context), vec![
+            Sent::Key { keycode: KeyCode::KeycodeMediaNext, down: true, long_press: false },
+            Sent::Key { keycode: KeyCode::KeycodeMediaNext, down: false, long_press: false },
+        ]);
+
+        drop(server);
+        assert_eq!(std::fs::read_dir(&art_dir).unwrap().count(), 0);
+        std::fs::remove_dir(&art_dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn unwritable_art_dir() {
+        let daemon = DBusDaemon::start();
+
+        let (server, mut playback, _) = setup(&daemon, "unwritable", PathBuf::from("/nonexistent/anauuno"));
+        let client = zbus::blocking::connection::Builder::address(daemon.address.as_str()).unwrap().build().unwrap();
+
+        metadata(&mut playback, "Song", b"\x89PNG\r\n\x1a\nart");
+        server.refresh().unwrap();
+
+        // Published without the art
+        let metadata = player_metadata(&client, "unwritable");
+        assert_eq!(string(&metadata, "xesam:title").as_deref(), Some("Song"));
+        assert_eq!(string(&metadata, "mpris:artUrl"), None);
+    }
+}
@@ -342,17 +342,17 @@ impl NavigationMessageType {
 }
 
 pub enum MediaPlaybackMessageType {
-    PlaybackMetadata = 0x8001,
-    PlaybackStartResponse = 0x8002,
-    PlaybackMetaDataStart = 0x8003,
+    PlaybackStatus = 0x8001,
+    PlaybackInput = 0x8002,
+    PlaybackMetadata = 0x8003,
 }
 
 impl MediaPlaybackMessageType {
     pub fn from_u16(value: u16) -> Option<Self> {
         match value {
-            0x8001 => Some(MediaPlaybackMessageType::PlaybackMetadata),
-            0x8002 => Some(MediaPlaybackMessageType::PlaybackStartResponse),
-            0x8003 => Some(MediaPlaybackMessageType::PlaybackMetaDataStart),
+            0x8001 => Some(MediaPlaybackMessageType::PlaybackStatus),
+            0x8002 => Some(MediaPlaybackMessageType::PlaybackInput),
+            0x8003 => Some(MediaPlaybackMessageType::PlaybackMetadata),
             _ => None,
         }
     }
@@ -375,6 +375,21 @@ impl SensorsMessageType {
     }
 }
 
+pub enum PhoneStatusMessageType {
+    PhoneStatus = 0x8001,
+    PhoneStatusInput = 0x8002,
+}
+
+impl PhoneStatusMessageType {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+             0x8001 => Some(PhoneStatusMessageType::PhoneStatus),
+             0x8002 => Some(PhoneStatusMessageType::PhoneStatusInput),
+            _ => None,
+        }
+    }
+}
+
 
 pub struct UnitedMessageType {
     pub service_type: ServiceType,
@@ -434,3 +449,12 @@ impl From<SensorsMessageType> for UnitedMessageType {
         }
     }
 }
+
+impl From<PhoneStatusMessageType> for UnitedMessageType {
+    fn from(value: PhoneStatusMessageType) -> Self {
+        UnitedMessageType {
+            service_type: ServiceType::PhoneStatus,
+            message_type: value as u16,
+        }
+    }
+}
@@ -0,0 +1,150 @@
+pub const NAL_TYPE_IDR: u8 = 5;
+pub const NAL_TYPE_SPS: u8 = 7;
+pub const NAL_TYPE_PPS: u8 = 8;
+pub const NAL_TYPE_AUD: u8 = 9;
+
+pub fn nal_type(nal: &[u8]) -> u8 {
+    nal.first().map(|header| header & 0x1F).unwrap_or(0)
+}
+
+// Splits an Annex-B byte stream (00 00 01 / 00 00 00 01 start codes) into NAL units
+pub fn split_annex_b(data: &[u8]) -> Vec<&[u8]> {
+    let mut nals = vec![];
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(start) = start {
+                nals.push(trim_trailing_zeros(&data[start..i]));
+            }
+
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    match start {
+        Some(start) => nals.push(&data[start..]),
+        // No start code at all, treat the whole buffer as a single NAL unit
+        None if !data.is_empty() => nals.push(data),
+        None => {}
+    }
+
+    nals.into_iter().filter(|nal| !nal.is_empty()).collect()
+}
+
+fn trim_trailing_zeros(nal: &[u8]) -> &[u8] {
+    let mut end = nal.len();
+    while end > 0 && nal[end - 1] == 0 {
+        end -= 1;
+    }
+
+    &nal[..end]
+}
+
+// Converts Annex-B NAL units into the 4 byte length prefixed form used by MP4/Matroska
+pub fn to_length_prefixed(nals: &[&[u8]]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(nals.iter().map(|nal| nal.len() + 4).sum());
+
+    for nal in nals {
+        buf.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        buf.extend_from_slice(nal);
+    }
+
+    buf
+}
+
+// Builds an AVCDecoderConfigurationRecord (avcC) from the SPS and PPS NAL units
+pub fn avc_decoder_configuration(nals: &[&[u8]]) -> Option<Vec<u8>> {
+    let sps: Vec<&[u8]> = nals.iter().copied().filter(|nal| nal_type(nal) == NAL_TYPE_SPS).collect();
+    let pps: Vec<&[u8]> = nals.iter().copied().filter(|nal| nal_type(nal) == NAL_TYPE_PPS).collect();
+
+    let first_sps = sps.first()?;
+    if first_sps.len() < 4 || pps.is_empty() {
+        return None;
+    }
+
+    let mut record = vec![
+        1,            // configurationVersion
+        first_sps[1], // AVCProfileIndication
+        first_sps[2], // profile_compatibility
+        first_sps[3], // AVCLevelIndication
+        0xFF,         // lengthSizeMinusOne = 3
+        0xE0 | (sps.len() as u8 & 0x1F),
+    ];
+
+    for sps in &sps {
+        record.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        record.extend_from_slice(sps);
+    }
+
+    record.push(pps.len() as u8);
+    for pps in &pps {
+        record.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        record.extend_from_slice(pps);
+    }
+
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: [u8; 5] = [0x67, 0x64, 0x00, 0x1F, 0xAC];
+    const PPS: [u8; 3] = [0x68, 0xEE, 0x3C];
+    const IDR: [u8; 3] = [0x65, 0x88, 0x84];
+
+    #[test]
+    fn annex_b() {
+        let data = [
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x1F, 0xAC,
+            0x00, 0x00, 0x01, 0x68, 0xEE, 0x3C,
+            0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84,
+        ];
+
+        assert_eq!(split_annex_b(&data), vec![&SPS[..], &PPS[..], &IDR[..]]);
+    }
+
+    #[test]
+    fn annex_b_edge_cases() {
+        // Trailing zeros of a NAL unit belong to the next start code
+        assert_eq!(split_annex_b(&[0x00, 0x00, 0x01, 0x09, 0xF0, 0x00, 0x00, 0x00, 0x00, 0x01, 0x65]), vec![&[0x09, 0xF0][..], &[0x65][..]]);
+        // No start code
+        assert_eq!(split_annex_b(&[0x65, 0x88]), vec![&[0x65, 0x88][..]]);
+        // Empty NAL units are dropped
+        assert_eq!(split_annex_b(&[0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x65]), vec![&[0x65][..]]);
+        assert_eq!(split_annex_b(&[0x00, 0x00, 0x01]), Vec::<&[u8]>::new());
+        assert_eq!(split_annex_b(&[]), Vec::<&[u8]>::new());
+    }
+
+    #[test]
+    fn nal_types() {
+        assert_eq!(nal_type(&SPS), NAL_TYPE_SPS);
+        assert_eq!(nal_type(&PPS), NAL_TYPE_PPS);
+        assert_eq!(nal_type(&IDR), NAL_TYPE_IDR);
+        assert_eq!(nal_type(&[]), 0);
+    }
+
+    #[test]
+    fn length_prefixed() {
+        assert_eq!(to_length_prefixed(&[&PPS, &[0x41]]), [0x00, 0x00, 0x00, 0x03, 0x68, 0xEE, 0x3C, 0x00, 0x00, 0x00, 0x01, 0x41]);
+    }
+
+    #[test]
+    fn avcc() {
+        assert_eq!(avc_decoder_configuration(&[&SPS, &PPS, &IDR]), Some(vec![
+            0x01, 0x64, 0x00, 0x1F, 0xFF,
+            0xE1, 0x00, 0x05, 0x67, 0x64, 0x00, 0x1F, 0xAC,
+            0x01, 0x00, 0x03, 0x68, 0xEE, 0x3C,
+        ]));
+
+        assert_eq!(avc_decoder_configuration(&[&SPS, &IDR]), None);
+        assert_eq!(avc_decoder_configuration(&[&PPS]), None);
+        // Too short to carry the profile and level
+        assert_eq!(avc_decoder_configuration(&[&[0x67, 0x64], &PPS]), None);
+    }
+}
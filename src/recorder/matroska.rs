@@ -0,0 +1,409 @@
+use std::io::{Seek, SeekFrom, Write};
+
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+
+const CLUSTER: u32 = 0x1F43B675;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const CUES: u32 = 0x1C53BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK: u64 = 1;
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+// Matroska stores block timestamps as i16 relative to the cluster timestamp
+const MAX_CLUSTER_DURATION_MS: u64 = i16::MAX as u64;
+
+pub struct MatroskaVideoTrack {
+    pub codec_id: String,
+    pub codec_private: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+struct Cluster {
+    timestamp_ms: u64,
+    data: Vec<u8>,
+}
+
+// Single track Matroska muxer that writes a cluster whenever a new keyframe starts one,
+// so everything up to the last finished cluster stays playable if the process dies.
+pub struct MatroskaWriter<W: Write + Seek> {
+    writer: W,
+    segment_size_position: u64,
+    segment_data_position: u64,
+    duration_position: u64,
+    cluster: Option<Cluster>,
+    cues: Vec<(u64, u64)>,
+    last_timestamp_ms: u64,
+}
+
+impl<W: Write + Seek> MatroskaWriter<W> {
+    pub fn new(mut writer: W, track: &MatroskaVideoTrack) -> crate::error::Result<Self> {
+        let mut header = vec![];
+        write_uint(&mut header, EBML_VERSION, 1);
+        write_uint(&mut header, EBML_READ_VERSION, 1);
+        write_uint(&mut header, EBML_MAX_ID_LENGTH, 4);
+        write_uint(&mut header, EBML_MAX_SIZE_LENGTH, 8);
+        write_string(&mut header, DOC_TYPE, "matroska");
+        write_uint(&mut header, DOC_TYPE_VERSION, 4);
+        write_uint(&mut header, DOC_TYPE_READ_VERSION, 2);
+
+        let mut buf = vec![];
+        write_master(&mut buf, EBML, &header);
+        writer.write_all(&buf)?;
+
+        // The Segment size is patched in `finish`
+        let mut buf = vec![];
+        write_id(&mut buf, SEGMENT);
+        writer.write_all(&buf)?;
+        let segment_size_position = writer.stream_position()?;
+        writer.write_all(&UNKNOWN_SIZE)?;
+        let segment_data_position = writer.stream_position()?;
+
+        let mut info = vec![];
+        write_uint(&mut info, TIMESTAMP_SCALE, 1_000_000);
+        write_string(&mut info, MUXING_APP, "anauuno");
+        write_string(&mut info, WRITING_APP, "anauuno");
+        write_id(&mut info, DURATION);
+        write_size(&mut info, 8);
+        let duration_offset = info.len() as u64;
+        info.extend_from_slice(&0f64.to_be_bytes());
+
+        let mut buf = vec![];
+        write_id(&mut buf, INFO);
+        write_size(&mut buf, info.len() as u64);
+        let duration_position = segment_data_position + buf.len() as u64 + duration_offset;
+        buf.extend_from_slice(&info);
+
+        let mut video = vec![];
+        write_uint(&mut video, PIXEL_WIDTH, track.width as u64);
+        write_uint(&mut video, PIXEL_HEIGHT, track.height as u64);
+
+        let mut entry = vec![];
+        write_uint(&mut entry, TRACK_NUMBER, TRACK);
+        write_uint(&mut entry, TRACK_UID, TRACK);
+        write_uint(&mut entry, TRACK_TYPE, TRACK_TYPE_VIDEO);
+        write_uint(&mut entry, FLAG_LACING, 0);
+        write_string(&mut entry, CODEC_ID, &track.codec_id);
+        write_binary(&mut entry, CODEC_PRIVATE, &track.codec_private);
+        write_master(&mut entry, VIDEO, &video);
+
+        let mut tracks = vec![];
+        write_master(&mut tracks, TRACK_ENTRY, &entry);
+        write_master(&mut buf, TRACKS, &tracks);
+
+        writer.write_all(&buf)?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+            segment_size_position,
+            segment_data_position,
+            duration_position,
+            cluster: None,
+            cues: vec![],
+            last_timestamp_ms: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, timestamp_ms: u64, keyframe: bool, data: &[u8]) -> crate::error::Result<()> {
+        let timestamp_ms = timestamp_ms.max(self.last_timestamp_ms);
+        self.last_timestamp_ms = timestamp_ms;
+
+        let start_new_cluster = match &self.cluster {
+            None => true,
+            Some(cluster) => {
+                let relative = timestamp_ms - cluster.timestamp_ms;
+                (keyframe && relative > 0) || relative > MAX_CLUSTER_DURATION_MS
+            }
+        };
+
+        if start_new_cluster {
+            self.flush_cluster()?;
+
+            if keyframe {
+                let position = self.writer.stream_position()? - self.segment_data_position;
+                self.cues.push((timestamp_ms, position));
+            }
+
+            let mut data = vec![];
+            write_uint(&mut data, CLUSTER_TIMESTAMP, timestamp_ms);
+
+            self.cluster = Some(Cluster { timestamp_ms, data });
+        }
+
+        let cluster = self.cluster.as_mut().unwrap();
+        let relative = (timestamp_ms - cluster.timestamp_ms) as i16;
+
+        let mut block = Vec::with_capacity(data.len() + 4);
+        block.push(0x80 | TRACK as u8);
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if keyframe { 0x80 } else { 0x00 });
+        block.extend_from_slice(data);
+
+        write_binary(&mut cluster.data, SIMPLE_BLOCK, &block);
+
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> crate::error::Result<()> {
+        self.flush_cluster()?;
+
+        let mut cue_points = vec![];
+        for (timestamp_ms, position) in &self.cues {
+            let mut positions = vec![];
+            write_uint(&mut positions, CUE_TRACK, TRACK);
+            write_uint(&mut positions, CUE_CLUSTER_POSITION, *position);
+
+            let mut cue_point = vec![];
+            write_uint(&mut cue_point, CUE_TIME, *timestamp_ms);
+            write_master(&mut cue_point, CUE_TRACK_POSITIONS, &positions);
+
+            write_master(&mut cue_points, CUE_POINT, &cue_point);
+        }
+
+        if !cue_points.is_empty() {
+            let mut buf = vec![];
+            write_master(&mut buf, CUES, &cue_points);
+            self.writer.write_all(&buf)?;
+        }
+
+        let end_position = self.writer.stream_position()?;
+        let segment_size = end_position - self.segment_data_position;
+
+        let mut size = [0u8; 8];
+        size[0] = 0x01;
+        size[1..].copy_from_slice(&segment_size.to_be_bytes()[1..]);
+
+        self.writer.seek(SeekFrom::Start(self.segment_size_position))?;
+        self.writer.write_all(&size)?;
+
+        self.writer.seek(SeekFrom::Start(self.duration_position))?;
+        self.writer.write_all(&(self.last_timestamp_ms as f64).to_be_bytes())?;
+
+        self.writer.seek(SeekFrom::Start(end_position))?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn flush_cluster(&mut self) -> crate::error::Result<()> {
+        if let Some(cluster) = self.cluster.take() {
+            let mut buf = Vec::with_capacity(cluster.data.len() + 12);
+            write_master(&mut buf, CLUSTER, &cluster.data);
+
+            self.writer.write_all(&buf)?;
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+fn write_size(buf: &mut Vec<u8>, size: u64) {
+    // A size with all value bits set means "unknown", so stay one below the limit
+    let mut length = 1;
+    while length < 8 && size >= (1u64 << (7 * length)) - 1 {
+        length += 1;
+    }
+
+    let marked = size | (1u64 << (7 * length));
+    buf.extend_from_slice(&marked.to_be_bytes()[8 - length..]);
+}
+
+fn write_master(buf: &mut Vec<u8>, id: u32, content: &[u8]) {
+    write_binary(buf, id, content);
+}
+
+fn write_binary(buf: &mut Vec<u8>, id: u32, content: &[u8]) {
+    write_id(buf, id);
+    write_size(buf, content.len() as u64);
+    buf.extend_from_slice(content);
+}
+
+fn write_uint(buf: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|byte| **byte == 0).count().min(7);
+    write_binary(buf, id, &bytes[skip..]);
+}
+
+fn write_string(buf: &mut Vec<u8>, id: u32, value: &str) {
+    write_binary(buf, id, value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn size(size: u64) -> Vec<u8> {
+        let mut buf = vec![];
+        write_size(&mut buf, size);
+        buf
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|window| window == needle)
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(size(0), [0x80]);
+        assert_eq!(size(1), [0x81]);
+        assert_eq!(size(126), [0xFE]);
+        // 127 with one byte would be 0xFF, the reserved "unknown" size
+        assert_eq!(size(127), [0x40, 0x7F]);
+        assert_eq!(size(16382), [0x7F, 0xFE]);
+        assert_eq!(size(16383), [0x20, 0x3F, 0xFF]);
+        assert_eq!(size(0x1F_FFFE), [0x3F, 0xFF, 0xFE]);
+        assert_eq!(size(0x1F_FFFF), [0x10, 0x1F, 0xFF, 0xFF]);
+        assert_eq!(size(1 << 40), [0x05, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn elements() {
+        let mut buf = vec![];
+        write_id(&mut buf, SIMPLE_BLOCK);
+        write_id(&mut buf, DURATION);
+        write_id(&mut buf, TIMESTAMP_SCALE);
+        write_id(&mut buf, SEGMENT);
+        assert_eq!(buf, [0xA3, 0x44, 0x89, 0x2A, 0xD7, 0xB1, 0x18, 0x53, 0x80, 0x67]);
+
+        let mut buf = vec![];
+        write_uint(&mut buf, TRACK_NUMBER, 0);
+        write_uint(&mut buf, TRACK_NUMBER, 1);
+        write_uint(&mut buf, TIMESTAMP_SCALE, 1_000_000);
+        assert_eq!(buf, [0xD7, 0x81, 0x00, 0xD7, 0x81, 0x01, 0x2A, 0xD7, 0xB1, 0x83, 0x0F, 0x42, 0x40]);
+
+        let mut buf = vec![];
+        write_string(&mut buf, CODEC_ID, "V_VP8");
+        assert_eq!(buf, [0x86, 0x85, b'V', b'_', b'V', b'P', b'8']);
+    }
+
+    #[test]
+    fn file() {
+        let track = MatroskaVideoTrack {
+            codec_id: "V_MPEG4/ISO/AVC".to_owned(),
+            codec_private: vec![0x01, 0x02],
+            width: 800,
+            height: 480,
+        };
+
+        let mut writer = MatroskaWriter::new(Cursor::new(vec![]), &track).unwrap();
+        writer.write_frame(0, true, &[0xAA]).unwrap();
+        writer.write_frame(40, false, &[0xBB]).unwrap();
+        writer.finish().unwrap();
+        let file = writer.into_inner().into_inner();
+
+        let mut header = vec![0x1A, 0x45, 0xDF, 0xA3, 0xA3];
+        header.extend_from_slice(&[0x42, 0x86, 0x81, 0x01, 0x42, 0xF7, 0x81, 0x01, 0x42, 0xF2, 0x81, 0x04, 0x42, 0xF3, 0x81, 0x08]);
+        header.extend_from_slice(&[0x42, 0x82, 0x88]);
+        header.extend_from_slice(b"matroska");
+        header.extend_from_slice(&[0x42, 0x87, 0x81, 0x04, 0x42, 0x85, 0x81, 0x02]);
+        header.extend_from_slice(&[0x18, 0x53, 0x80, 0x67]);
+        assert_eq!(file[..header.len()], header);
+
+        // Segment size, patched with the length of everything after it
+        let segment_data = header.len() + 8;
+        let mut segment_size = [0u8; 8];
+        segment_size[0] = 0x01;
+        segment_size[1..].copy_from_slice(&((file.len() - segment_data) as u64).to_be_bytes()[1..]);
+        assert_eq!(file[header.len()..segment_data], segment_size);
+
+        // Duration, patched with the last timestamp
+        let duration = find(&file, &[0x44, 0x89, 0x88]).unwrap() + 3;
+        assert_eq!(file[duration..duration + 8], 40f64.to_be_bytes());
+
+        let mut codec_private = vec![0x63, 0xA2, 0x82, 0x01, 0x02];
+        codec_private.extend_from_slice(&[0xE0, 0x88, 0xB0, 0x82, 0x03, 0x20, 0xBA, 0x82, 0x01, 0xE0]);
+        assert!(find(&file, &codec_private).is_some());
+
+        let cluster = [
+            0x1F, 0x43, 0xB6, 0x75, 0x91,
+            0xE7, 0x81, 0x00,
+            0xA3, 0x85, 0x81, 0x00, 0x00, 0x80, 0xAA,
+            0xA3, 0x85, 0x81, 0x00, 0x28, 0x00, 0xBB,
+        ];
+        let cluster_position = find(&file, &cluster).unwrap();
+
+        let cues = [
+            0x1C, 0x53, 0xBB, 0x6B, 0x8D,
+            0xBB, 0x8B,
+            0xB3, 0x81, 0x00,
+            0xB7, 0x86, 0xF7, 0x81, 0x01, 0xF1, 0x81, (cluster_position - segment_data) as u8,
+        ];
+        assert_eq!(file[cluster_position + cluster.len()..], cues);
+    }
+
+    #[test]
+    fn clusters_start_at_keyframes() {
+        let track = MatroskaVideoTrack {
+            codec_id: "V_MPEG4/ISO/AVC".to_owned(),
+            codec_private: vec![],
+            width: 1,
+            height: 1,
+        };
+
+        let mut writer = MatroskaWriter::new(Cursor::new(vec![]), &track).unwrap();
+        writer.write_frame(0, true, &[0xAA]).unwrap();
+        writer.write_frame(33, false, &[0xBB]).unwrap();
+        writer.write_frame(1000, true, &[0xCC]).unwrap();
+        // Going backwards is clamped to the last timestamp
+        writer.write_frame(900, false, &[0xDD]).unwrap();
+        writer.finish().unwrap();
+        let file = writer.into_inner().into_inner();
+
+        let second_cluster = [
+            0x1F, 0x43, 0xB6, 0x75, 0x92,
+            0xE7, 0x82, 0x03, 0xE8,
+            0xA3, 0x85, 0x81, 0x00, 0x00, 0x80, 0xCC,
+            0xA3, 0x85, 0x81, 0x00, 0x00, 0x00, 0xDD,
+        ];
+        assert!(find(&file, &second_cluster).is_some());
+        assert_eq!(file.windows(4).filter(|window| *window == [0x1F, 0x43, 0xB6, 0x75]).count(), 2);
+        assert_eq!(file.windows(4).filter(|window| *window == [0xF7, 0x81, 0x01, 0xF1]).count(), 2);
+    }
+}
@@ -0,0 +1,194 @@
+pub mod h264;
+pub mod matroska;
+
+use crate::recorder::matroska::{MatroskaVideoTrack, MatroskaWriter};
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+
+const CODEC_ID_H264: &str = "V_MPEG4/ISO/AVC";
+
+// Muxes the projected H.264 stream of a VideoService into a Matroska (.mkv) file.
+// Frames received before the codec configuration are dropped, as they can't be decoded anyway.
+pub struct VideoRecorder<W: Write + Seek = BufWriter<File>> {
+    writer: Option<W>,
+    matroska: Option<MatroskaWriter<W>>,
+    // avcC of the latest codec config
+    codec_private: Vec<u8>,
+    // SPS/PPS of a config that changed after the track header was written
+    in_band_config: Vec<Vec<u8>>,
+    width: u32,
+    height: u32,
+    first_timestamp: Option<u64>,
+    finished: bool,
+}
+
+impl VideoRecorder {
+    pub fn create<P: AsRef<Path>>(path: P, width: u32, height: u32) -> crate::error::Result<Self> {
+        let file = File::create(path)?;
+
+        Ok(Self::new(BufWriter::new(file), width, height))
+    }
+}
+
+impl<W: Write + Seek> VideoRecorder<W> {
+    pub fn new(writer: W, width: u32, height: u32) -> Self {
+        Self {
+            writer: Some(writer),
+            matroska: None,
+            codec_private: vec![],
+            in_band_config: vec![],
+            width,
+            height,
+            first_timestamp: None,
+            finished: false,
+        }
+    }
+
+    // Codec config as sent with `MediaMessageType::CodecData` (Annex-B SPS/PPS)
+    pub fn write_codec_config(&mut self, data: &[u8]) -> crate::error::Result<()> {
+        let nals = h264::split_annex_b(data);
+        let Some(codec_private) = h264::avc_decoder_configuration(&nals) else {
+            return Ok(());
+        };
+
+        if self.matroska.is_none() {
+            return self.start(codec_private);
+        }
+
+        // The track header is already written, a changed config (e.g. a new resolution)
+        // goes in-band in front of the next frame
+        if codec_private != self.codec_private {
+            self.codec_private = codec_private;
+            self.in_band_config = nals.into_iter()
+                .filter(|nal| matches!(h264::nal_type(nal), h264::NAL_TYPE_SPS | h264::NAL_TYPE_PPS))
+                .map(|nal| nal.to_vec())
+                .collect();
+        }
+
+        Ok(())
+    }
+
+    // A frame as sent with `MediaMessageType::MediaData`, the timestamp is in microseconds
+    pub fn write_frame(&mut self, timestamp: u64, data: &[u8]) -> crate::error::Result<()> {
+        let nals = h264::split_annex_b(data);
+
+        if self.matroska.is_none() {
+            // Some phones send SPS/PPS in-band with the first keyframe instead
+            match h264::avc_decoder_configuration(&nals) {
+                Some(codec_private) => self.start(codec_private)?,
+                None => return Ok(()),
+            }
+        }
+
+        let in_band_config = std::mem::take(&mut self.in_band_config);
+
+        let nals: Vec<&[u8]> = in_band_config.iter().map(Vec::as_slice)
+            .chain(nals)
+            .filter(|nal| h264::nal_type(nal) != h264::NAL_TYPE_AUD)
+            .collect();
+
+        if nals.is_empty() {
+            return Ok(());
+        }
+
+        let keyframe = nals.iter().any(|nal| h264::nal_type(nal) == h264::NAL_TYPE_IDR);
+
+        let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+        let timestamp_ms = timestamp.saturating_sub(first_timestamp) / 1000;
+
+        let block = h264::to_length_prefixed(&nals);
+        self.matroska.as_mut().unwrap().write_frame(timestamp_ms, keyframe, &block)
+    }
+
+    pub fn finish(&mut self) -> crate::error::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        match self.matroska.as_mut() {
+            Some(matroska) => matroska.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn start(&mut self, codec_private: Vec<u8>) -> crate::error::Result<()> {
+        self.codec_private = codec_private.clone();
+
+        let track = MatroskaVideoTrack {
+            codec_id: CODEC_ID_H264.to_owned(),
+            codec_private,
+            width: self.width,
+            height: self.height,
+        };
+
+        let writer = self.writer.take().ok_or(crate::error::Error::IoOther)?;
+        self.matroska = Some(MatroskaWriter::new(writer, &track)?);
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for VideoRecorder<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            println!("Failed to finish video recording: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const CONFIG: [u8; 16] = [0, 0, 0, 1, 0x67, 0x64, 0x00, 0x1F, 0xAC, 0, 0, 0, 1, 0x68, 0xEE, 0x3C];
+    const NEW_CONFIG: [u8; 16] = [0, 0, 0, 1, 0x67, 0x64, 0x00, 0x28, 0xAD, 0, 0, 0, 1, 0x68, 0xEE, 0x3D];
+    const IDR: [u8; 7] = [0, 0, 0, 1, 0x65, 0x88, 0x84];
+
+    fn record(write: impl FnOnce(&mut VideoRecorder<Cursor<&mut Vec<u8>>>)) -> Vec<u8> {
+        let mut file = vec![];
+        let mut recorder = VideoRecorder::new(Cursor::new(&mut file), 800, 480);
+        write(&mut recorder);
+        drop(recorder);
+
+        file
+    }
+
+    fn count(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack.windows(needle.len()).filter(|window| *window == needle).count()
+    }
+
+    #[test]
+    fn frames_before_config_are_dropped() {
+        let file = record(|recorder| {
+            recorder.write_frame(0, &IDR).unwrap();
+        });
+
+        assert!(file.is_empty());
+    }
+
+    #[test]
+    fn changed_config_goes_in_band() {
+        let file = record(|recorder| {
+            recorder.write_codec_config(&CONFIG).unwrap();
+            recorder.write_frame(0, &IDR).unwrap();
+            // The same config again isn't repeated
+            recorder.write_codec_config(&CONFIG).unwrap();
+            recorder.write_frame(33_000, &IDR).unwrap();
+            recorder.write_codec_config(&NEW_CONFIG).unwrap();
+            recorder.write_frame(66_000, &IDR).unwrap();
+            recorder.write_frame(99_000, &IDR).unwrap();
+        });
+
+        // The track header keeps the first config
+        assert_eq!(count(&file, &[0x67, 0x64, 0x00, 0x1F, 0xAC]), 1);
+
+        let mut frame = vec![0x00, 0x00, 0x00, 0x05, 0x67, 0x64, 0x00, 0x28, 0xAD];
+        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x03, 0x68, 0xEE, 0x3D]);
+        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x03, 0x65, 0x88, 0x84]);
+        assert_eq!(count(&file, &frame), 1);
+        assert_eq!(count(&file, &[0x00, 0x00, 0x00, 0x03, 0x65, 0x88, 0x84]), 4);
+    }
+}
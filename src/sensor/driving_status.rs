@@ -0,0 +1,319 @@
+use crate::service::sensor::{DrivingStatus, Gear, SensorHandle, SensorReading};
+use std::time::{Duration, Instant};
+
+// Restrictions usually required while the vehicle moves, voice input stays available
+pub const DRIVING_RESTRICTIONS: i32 = DrivingStatus::NoVideo as i32
+    | DrivingStatus::NoKeyboardInput as i32
+    | DrivingStatus::NoConfig as i32
+    | DrivingStatus::LimitMessageLen as i32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DrivingStatusConfig {
+    // m/s at which the vehicle counts as moving
+    pub moving_speed: f64,
+    // m/s below which a moving vehicle counts as stopped again, lower than `moving_speed`
+    pub stopped_speed: f64,
+    // Restriction bitmasks of `DrivingStatus` values
+    pub moving_restrictions: i32,
+    // Standing, but neither in park nor with the parking brake engaged (e.g. at a traffic light)
+    pub stopped_restrictions: i32,
+    pub parked_restrictions: i32,
+    // A less restrictive state has to hold this long before it is reported
+    pub release_delay: Duration,
+}
+
+impl Default for DrivingStatusConfig {
+    fn default() -> Self {
+        Self {
+            moving_speed: 2.0,
+            stopped_speed: 0.5,
+            moving_restrictions: DRIVING_RESTRICTIONS,
+            stopped_restrictions: DRIVING_RESTRICTIONS,
+            parked_restrictions: DrivingStatus::Unrestricted as i32,
+            release_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+// Derives the driving status from speed, gear and parking brake readings and pushes it to the
+// phone whenever it changes. Restrictions are tightened immediately and relaxed after
+// `release_delay`, which is checked on every update and `tick`.
+pub struct DrivingStatusPolicy {
+    config: DrivingStatusConfig,
+    handle: SensorHandle,
+    speed: Option<f64>,
+    location_speed: Option<f64>,
+    gear: Option<Gear>,
+    parking_brake: Option<bool>,
+    moving: bool,
+    status: i32,
+    // Less restrictive status waiting for `release_delay`
+    pending: Option<(i32, Instant)>,
+}
+
+impl DrivingStatusPolicy {
+    // Starts out with the moving restrictions until readings prove otherwise
+    pub fn new(config: DrivingStatusConfig, handle: SensorHandle) -> Self {
+        let policy = Self {
+            config,
+            handle,
+            speed: None,
+            location_speed: None,
+            gear: None,
+            parking_brake: None,
+            moving: true,
+            status: config.moving_restrictions,
+            pending: None,
+        };
+
+        policy.handle.push(SensorReading::DrivingStatus(policy.status));
+
+        policy
+    }
+
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    // Takes Speed, Gear and ParkingBrake readings (and the speed of Location readings),
+    // everything else is ignored
+    pub fn update(&mut self, reading: &SensorReading) {
+        self.update_at(reading, Instant::now());
+    }
+
+    pub fn tick(&mut self) {
+        self.evaluate(Instant::now());
+    }
+
+    fn update_at(&mut self, reading: &SensorReading, now: Instant) {
+        match reading {
+            SensorReading::Speed { speed, .. } => self.speed = Some(*speed),
+            SensorReading::Location(location) if location.speed.is_some() => self.location_speed = location.speed,
+            SensorReading::Gear(gear) => self.gear = Some(*gear),
+            SensorReading::ParkingBrake(engaged) => self.parking_brake = Some(*engaged),
+            _ => return,
+        }
+
+        // The GPS speed is only used as long as there is no vehicle speed, without any speed
+        // a parked vehicle is assumed to stand still
+        self.moving = match self.speed.or(self.location_speed) {
+            Some(speed) => self.is_moving(speed),
+            None => !self.is_parked(),
+        };
+
+        self.evaluate(now);
+    }
+
+    fn is_moving(&self, speed: f64) -> bool {
+        let speed = speed.abs();
+
+        if self.moving {
+            speed > self.config.stopped_speed
+        } else {
+            speed >= self.config.moving_speed
+        }
+    }
+
+    fn is_parked(&self) -> bool {
+        self.gear == Some(Gear::Park) || self.parking_brake == Some(true)
+    }
+
+    fn target_status(&self) -> i32 {
+        if self.moving {
+            return self.config.moving_restrictions;
+        }
+
+        if self.is_parked() {
+            self.config.parked_restrictions
+        } else {
+            self.config.stopped_restrictions
+        }
+    }
+
+    fn evaluate(&mut self, now: Instant) {
+        let target = self.target_status();
+
+        if target == self.status {
+            self.pending = None;
+            return;
+        }
+
+        // Any restriction the current status lacks is applied right away
+        if target & !self.status != 0 {
+            self.pending = None;
+            self.set_status(target);
+            return;
+        }
+
+        let since = match self.pending {
+            Some((pending, since)) if pending == target => since,
+            _ => {
+                self.pending = Some((target, now));
+                now
+            }
+        };
+
+        if now.duration_since(since) >= self.config.release_delay {
+            self.pending = None;
+            self.set_status(target);
+        }
+    }
+
+    fn set_status(&mut self, status: i32) {
+        println!("DrivingStatusPolicy: {:#x} -> {:#x}", self.status, status);
+
+        self.status = status;
+        self.handle.push(SensorReading::DrivingStatus(status));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::sensor::testing::{last, sensor_service};
+    use crate::service::sensor::SensorType;
+
+    const UNRESTRICTED: i32 = DrivingStatus::Unrestricted as i32;
+
+    fn policy(config: DrivingStatusConfig) -> (DrivingStatusPolicy, SensorHandle) {
+        let (service, _) = sensor_service(&[SensorType::DrivingStatus]);
+        let handle = service.handle();
+
+        (DrivingStatusPolicy::new(config, handle.clone()), handle)
+    }
+
+    fn speed(speed: f64) -> SensorReading {
+        SensorReading::Speed { speed, cruise_engaged: None, cruise_set_speed: None }
+    }
+
+    // Restrictions while standing that differ from the moving ones
+    fn config() -> DrivingStatusConfig {
+        DrivingStatusConfig {
+            stopped_restrictions: DrivingStatus::NoVideo as i32,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn starts_restricted() {
+        let (policy, handle) = policy(config());
+
+        assert_eq!(policy.status(), DRIVING_RESTRICTIONS);
+        assert_eq!(last(&handle, SensorType::DrivingStatus), Some(SensorReading::DrivingStatus(DRIVING_RESTRICTIONS)));
+    }
+
+    #[test]
+    fn speed_hysteresis() {
+        let (mut policy, _) = policy(config());
+        let now = Instant::now();
+
+        policy.update_at(&speed(0.6), now);
+        assert!(policy.moving);
+        policy.update_at(&speed(0.5), now);
+        assert!(!policy.moving);
+
+        // Has to reach 2 m/s before it moves again
+        policy.update_at(&speed(1.9), now);
+        assert!(!policy.moving);
+        policy.update_at(&speed(2.0), now);
+        assert!(policy.moving);
+        policy.update_at(&speed(1.0), now);
+        assert!(policy.moving);
+
+        // Reversing counts as well
+        policy.update_at(&speed(0.0), now);
+        policy.update_at(&speed(-2.5), now);
+        assert!(policy.moving);
+    }
+
+    #[test]
+    fn location_speed_is_a_fallback() {
+        let (mut policy, _) = policy(config());
+        let now = Instant::now();
+
+        let mut location = crate::service::sensor::LocationReading::new(0.0, 0.0, 5.0);
+        location.speed = Some(0.0);
+        policy.update_at(&SensorReading::Location(location.clone()), now);
+        assert!(!policy.moving);
+
+        location.speed = Some(10.0);
+        policy.update_at(&SensorReading::Location(location.clone()), now);
+        assert!(policy.moving);
+
+        // The vehicle speed wins
+        policy.update_at(&speed(0.0), now);
+        policy.update_at(&SensorReading::Location(location), now);
+        assert!(!policy.moving);
+    }
+
+    #[test]
+    fn restrictions_are_relaxed_after_delay() {
+        let (mut policy, handle) = policy(config());
+        let start = Instant::now();
+
+        policy.update_at(&speed(0.0), start);
+        assert_eq!(policy.status(), DRIVING_RESTRICTIONS);
+
+        policy.evaluate(start + Duration::from_millis(1999));
+        assert_eq!(policy.status(), DRIVING_RESTRICTIONS);
+
+        policy.evaluate(start + Duration::from_secs(2));
+        assert_eq!(policy.status(), DrivingStatus::NoVideo as i32);
+        assert_eq!(last(&handle, SensorType::DrivingStatus), Some(SensorReading::DrivingStatus(DrivingStatus::NoVideo as i32)));
+
+        // Parked relaxes further, again after the delay
+        policy.update_at(&SensorReading::Gear(Gear::Park), start + Duration::from_secs(3));
+        assert_eq!(policy.status(), DrivingStatus::NoVideo as i32);
+        policy.evaluate(start + Duration::from_secs(5));
+        assert_eq!(policy.status(), UNRESTRICTED);
+    }
+
+    #[test]
+    fn restrictions_are_tightened_immediately() {
+        let (mut policy, handle) = policy(config());
+        let start = Instant::now();
+
+        policy.update_at(&SensorReading::ParkingBrake(true), start);
+        policy.update_at(&speed(0.0), start);
+        policy.evaluate(start + Duration::from_secs(2));
+        assert_eq!(policy.status(), UNRESTRICTED);
+
+        policy.update_at(&SensorReading::ParkingBrake(false), start + Duration::from_secs(3));
+        assert_eq!(policy.status(), DrivingStatus::NoVideo as i32);
+
+        policy.update_at(&speed(3.0), start + Duration::from_secs(3));
+        assert_eq!(policy.status(), DRIVING_RESTRICTIONS);
+        assert_eq!(last(&handle, SensorType::DrivingStatus), Some(SensorReading::DrivingStatus(DRIVING_RESTRICTIONS)));
+    }
+
+    #[test]
+    fn moving_again_cancels_release() {
+        let (mut policy, _) = policy(config());
+        let start = Instant::now();
+
+        policy.update_at(&speed(0.0), start);
+        policy.update_at(&speed(3.0), start + Duration::from_secs(1));
+        policy.update_at(&speed(0.0), start + Duration::from_secs(2));
+
+        // The delay starts over when it stops again
+        policy.evaluate(start + Duration::from_secs(3));
+        assert_eq!(policy.status(), DRIVING_RESTRICTIONS);
+
+        policy.evaluate(start + Duration::from_secs(4));
+        assert_eq!(policy.status(), DrivingStatus::NoVideo as i32);
+    }
+
+    #[test]
+    fn parked_without_speed() {
+        let (mut policy, _) = policy(config());
+        let start = Instant::now();
+
+        policy.update_at(&SensorReading::Gear(Gear::Drive), start);
+        assert!(policy.moving);
+
+        policy.update_at(&SensorReading::Gear(Gear::Park), start);
+        assert!(!policy.moving);
+        policy.evaluate(start + Duration::from_secs(2));
+        assert_eq!(policy.status(), UNRESTRICTED);
+    }
+}
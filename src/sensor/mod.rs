@@ -0,0 +1,5 @@
+pub mod driving_status;
+pub mod night_mode;
+pub mod nmea;
+#[cfg(feature = "socketcan")]
+pub mod socketcan;
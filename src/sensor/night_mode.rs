@@ -0,0 +1,363 @@
+use crate::service::sensor::{HeadlightState, SensorHandle, SensorReading};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug)]
+pub struct NightModeConfig {
+    // Ambient light in lux below which it is night, it is day again above `day_lux`
+    pub night_lux: f64,
+    pub day_lux: f64,
+    // Follow the headlights when there is no light sensor
+    pub use_headlights: bool,
+    // Sun elevation in degrees below which it is night when neither light sensor nor headlights
+    // are available, -0.833 is sunset
+    pub sun_elevation: f64,
+    // Day again once the sun is this many degrees above `sun_elevation`
+    pub sun_hysteresis: f64,
+    // A change has to hold this long before it is reported, so tunnels and bridges don't flicker
+    pub switch_delay: Duration,
+}
+
+impl Default for NightModeConfig {
+    fn default() -> Self {
+        Self {
+            night_lux: 10.0,
+            day_lux: 30.0,
+            use_headlights: true,
+            sun_elevation: -0.833,
+            sun_hysteresis: 1.0,
+            switch_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+// Decides between day and night from the best available source: an ambient light sensor,
+// the headlights or the position of the sun at the latest location. Call `tick` periodically
+// (e.g. every few seconds) so delayed switches and sunset/sunrise are picked up.
+pub struct NightModeProvider {
+    config: NightModeConfig,
+    handle: SensorHandle,
+    lux: Option<f64>,
+    headlights: Option<bool>,
+    // Latitude, longitude in degrees
+    location: Option<(f64, f64)>,
+    night: bool,
+    pending: Option<(bool, Instant)>,
+}
+
+impl NightModeProvider {
+    pub fn new(config: NightModeConfig, handle: SensorHandle) -> Self {
+        let provider = Self {
+            config,
+            handle,
+            lux: None,
+            headlights: None,
+            location: None,
+            night: false,
+            pending: None,
+        };
+
+        provider.handle.push(SensorReading::Night(false));
+
+        provider
+    }
+
+    pub fn is_night(&self) -> bool {
+        self.night
+    }
+
+    pub fn set_ambient_light(&mut self, lux: f64) {
+        self.lux = Some(lux);
+        self.evaluate();
+    }
+
+    pub fn set_headlights(&mut self, on: bool) {
+        self.headlights = Some(on);
+        self.evaluate();
+    }
+
+    pub fn set_location(&mut self, latitude: f64, longitude: f64) {
+        self.location = Some((latitude, longitude));
+        self.evaluate();
+    }
+
+    // Takes Light and Location readings, everything else is ignored
+    pub fn update(&mut self, reading: &SensorReading) {
+        match reading {
+            SensorReading::Light { headlight: Some(headlight), .. } => {
+                match headlight {
+                    HeadlightState::Headlight1 => self.set_headlights(false),
+                    HeadlightState::Headlight2 | HeadlightState::Headlight3 => self.set_headlights(true),
+                    // Unknown state
+                    HeadlightState::Headlight0 => {}
+                }
+            }
+            SensorReading::Location(location) => {
+                self.set_location(location.latitude, location.longitude);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.evaluate();
+    }
+
+    fn target(&self, time: f64) -> Option<bool> {
+        if let Some(lux) = self.lux {
+            return Some(if self.night { lux < self.config.day_lux } else { lux < self.config.night_lux });
+        }
+
+        if self.config.use_headlights && let Some(headlights) = self.headlights {
+            return Some(headlights);
+        }
+
+        let (latitude, longitude) = self.location?;
+        let elevation = sun_elevation(latitude, longitude, time);
+
+        Some(if self.night {
+            elevation < self.config.sun_elevation + self.config.sun_hysteresis
+        } else {
+            elevation < self.config.sun_elevation
+        })
+    }
+
+    fn evaluate(&mut self) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64();
+        self.evaluate_at(Instant::now(), time);
+    }
+
+    // `time` is the wall clock in seconds since the unix epoch, for the position of the sun
+    fn evaluate_at(&mut self, now: Instant, time: f64) {
+        let Some(target) = self.target(time) else {
+            return;
+        };
+
+        if target == self.night {
+            self.pending = None;
+            return;
+        }
+
+        let since = match self.pending {
+            Some((pending, since)) if pending == target => since,
+            _ => {
+                self.pending = Some((target, now));
+                now
+            }
+        };
+
+        if now.duration_since(since) >= self.config.switch_delay {
+            println!("NightModeProvider: night {}", target);
+
+            self.pending = None;
+            self.night = target;
+            self.handle.push(SensorReading::Night(target));
+        }
+    }
+}
+
+// Elevation of the sun in degrees above the horizon, accurate to a fraction of a degree
+// which is plenty for day/night. `time` is in seconds since the unix epoch.
+pub fn sun_elevation(latitude: f64, longitude: f64, time: f64) -> f64 {
+    // Days since J2000.0 (2000-01-01 12:00 UTC)
+    let days = (time - 946_728_000.0) / 86_400.0;
+
+    let mean_anomaly = (357.529 + 0.985_600_28 * days).to_radians();
+    let mean_longitude = 280.459 + 0.985_647_36 * days;
+    let ecliptic_longitude = (mean_longitude
+        + 1.915 * mean_anomaly.sin()
+        + 0.020 * (2.0 * mean_anomaly).sin()).to_radians();
+    let obliquity = (23.439 - 0.000_000_36 * days).to_radians();
+
+    let right_ascension = (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    let sidereal_time = 280.460_618_37 + 360.985_647_366_29 * days;
+    let hour_angle = (sidereal_time + longitude).to_radians() - right_ascension;
+
+    let latitude = latitude.to_radians();
+
+    (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::sensor::testing::{last, sensor_service};
+    use crate::service::sensor::SensorType;
+
+    // London
+    const LATITUDE: f64 = 51.5;
+    const LONGITUDE: f64 = 0.0;
+
+    // 2024-03-20 12:00 UTC, 2024-06-20 12:00 UTC, ...
+    const EQUINOX_NOON: f64 = 1_710_936_000.0;
+    const EQUINOX_MIDNIGHT: f64 = 1_710_892_800.0;
+    const SUMMER_NOON: f64 = 1_718_884_800.0;
+    const SUMMER_MIDNIGHT: f64 = 1_718_928_000.0;
+    const WINTER_NOON: f64 = 1_734_782_400.0;
+
+    fn provider(config: NightModeConfig) -> (NightModeProvider, SensorHandle) {
+        let (service, _) = sensor_service(&[SensorType::Night]);
+        let handle = service.handle();
+
+        (NightModeProvider::new(config, handle.clone()), handle)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.5, "{} is not close to {}", actual, expected);
+    }
+
+    #[test]
+    fn known_sun_elevations() {
+        // 90° - latitude + declination at noon, the sun is at most a degree or two
+        // off the meridian at 12:00 UTC on the prime meridian
+        assert_close(sun_elevation(LATITUDE, LONGITUDE, SUMMER_NOON), 61.9);
+        assert_close(sun_elevation(LATITUDE, LONGITUDE, SUMMER_MIDNIGHT), -15.0);
+        assert_close(sun_elevation(LATITUDE, LONGITUDE, WINTER_NOON), 15.0);
+        assert_close(sun_elevation(LATITUDE, LONGITUDE, EQUINOX_NOON), 38.6);
+        assert_close(sun_elevation(LATITUDE, LONGITUDE, EQUINOX_MIDNIGHT), -38.4);
+
+        // Equator on the equinox, the sun is nearly overhead
+        assert_close(sun_elevation(0.0, 0.0, EQUINOX_NOON), 88.1);
+        // Half a day later on the other side of the world
+        assert_close(sun_elevation(0.0, 180.0, EQUINOX_MIDNIGHT), 88.0);
+    }
+
+    #[test]
+    fn sun_position() {
+        let config = NightModeConfig { switch_delay: Duration::ZERO, ..Default::default() };
+        let (mut provider, handle) = provider(config);
+        assert_eq!(last(&handle, SensorType::Night), Some(SensorReading::Night(false)));
+
+        provider.location = Some((LATITUDE, LONGITUDE));
+        let now = Instant::now();
+
+        provider.evaluate_at(now, SUMMER_MIDNIGHT);
+        assert!(provider.is_night());
+        assert_eq!(last(&handle, SensorType::Night), Some(SensorReading::Night(true)));
+
+        provider.evaluate_at(now, SUMMER_NOON);
+        assert!(!provider.is_night());
+        assert_eq!(last(&handle, SensorType::Night), Some(SensorReading::Night(false)));
+    }
+
+    #[test]
+    fn sun_hysteresis() {
+        let config = NightModeConfig {
+            sun_elevation: 0.0,
+            sun_hysteresis: 10.0,
+            switch_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let (mut provider, _) = provider(config);
+
+        // Sunrise at the equator on the equinox is around 06:07 UTC, the sun rises 15° per hour
+        provider.location = Some((0.0, 0.0));
+        let now = Instant::now();
+        let sunrise = EQUINOX_MIDNIGHT + 6.0 * 3600.0 + 7.0 * 60.0;
+
+        provider.evaluate_at(now, sunrise - 3600.0);
+        assert!(provider.is_night());
+
+        // Above the night threshold, but not yet 10° above it
+        provider.evaluate_at(now, sunrise + 1800.0);
+        assert!(provider.is_night());
+
+        provider.evaluate_at(now, sunrise + 3600.0);
+        assert!(!provider.is_night());
+
+        // Back below 10°, but still above the night threshold
+        provider.evaluate_at(now, sunrise + 1800.0);
+        assert!(!provider.is_night());
+    }
+
+    #[test]
+    fn lux_hysteresis() {
+        let config = NightModeConfig { switch_delay: Duration::ZERO, ..Default::default() };
+        let (mut provider, handle) = provider(config);
+
+        provider.set_ambient_light(20.0);
+        assert!(!provider.is_night());
+
+        provider.set_ambient_light(9.0);
+        assert!(provider.is_night());
+        assert_eq!(last(&handle, SensorType::Night), Some(SensorReading::Night(true)));
+
+        // Between the thresholds it stays night
+        provider.set_ambient_light(20.0);
+        assert!(provider.is_night());
+
+        provider.set_ambient_light(30.0);
+        assert!(!provider.is_night());
+
+        // ... and stays day
+        provider.set_ambient_light(20.0);
+        assert!(!provider.is_night());
+    }
+
+    #[test]
+    fn source_priority() {
+        let config = NightModeConfig { switch_delay: Duration::ZERO, ..Default::default() };
+        let (mut provider, _) = provider(config);
+
+        // Headlights win over the sun
+        provider.location = Some((LATITUDE, LONGITUDE));
+        provider.set_headlights(true);
+        provider.evaluate_at(Instant::now(), SUMMER_NOON);
+        assert!(provider.is_night());
+
+        provider.update(&SensorReading::Light { headlight: Some(HeadlightState::Headlight1), turn_indicator: None, hazard_light_on: None });
+        provider.evaluate_at(Instant::now(), SUMMER_MIDNIGHT);
+        assert!(!provider.is_night());
+
+        // A light sensor wins over the headlights
+        provider.update(&SensorReading::Light { headlight: Some(HeadlightState::Headlight2), turn_indicator: None, hazard_light_on: None });
+        assert!(provider.is_night());
+        provider.set_ambient_light(1000.0);
+        assert!(!provider.is_night());
+    }
+
+    #[test]
+    fn headlights_can_be_ignored() {
+        let config = NightModeConfig { use_headlights: false, switch_delay: Duration::ZERO, ..Default::default() };
+        let (mut provider, _) = provider(config);
+
+        provider.set_headlights(true);
+        assert!(!provider.is_night());
+
+        provider.location = Some((LATITUDE, LONGITUDE));
+        provider.evaluate_at(Instant::now(), SUMMER_NOON);
+        assert!(!provider.is_night());
+    }
+
+    #[test]
+    fn switch_delay() {
+        let (mut provider, handle) = provider(NightModeConfig::default());
+        provider.lux = Some(1.0);
+
+        let start = Instant::now();
+        provider.evaluate_at(start, 0.0);
+        provider.evaluate_at(start + Duration::from_secs(4), 0.0);
+        assert!(!provider.is_night());
+        assert_eq!(last(&handle, SensorType::Night), Some(SensorReading::Night(false)));
+
+        provider.evaluate_at(start + Duration::from_secs(5), 0.0);
+        assert!(provider.is_night());
+        assert_eq!(last(&handle, SensorType::Night), Some(SensorReading::Night(true)));
+
+        // A tunnel: bright again for a moment restarts the delay
+        provider.lux = Some(100.0);
+        provider.evaluate_at(start + Duration::from_secs(6), 0.0);
+        provider.lux = Some(1.0);
+        provider.evaluate_at(start + Duration::from_secs(7), 0.0);
+        provider.lux = Some(100.0);
+        provider.evaluate_at(start + Duration::from_secs(8), 0.0);
+        provider.evaluate_at(start + Duration::from_secs(12), 0.0);
+        assert!(provider.is_night());
+
+        provider.evaluate_at(start + Duration::from_secs(13), 0.0);
+        assert!(!provider.is_night());
+    }
+}
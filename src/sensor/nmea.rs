@@ -0,0 +1,620 @@
+use crate::service::sensor::{GpsSatellite, LocationReading, SensorHandle, SensorReading};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+
+const KNOTS_TO_MPS: f64 = 0.514_444;
+
+// Typical user equivalent range error of a consumer receiver, to estimate accuracy from HDOP
+const UERE_METERS: f64 = 5.0;
+
+// Turns NMEA 0183 sentences (RMC, GGA, GSA, GSV, GST, VTG of any talker) into Location and
+// GpsSatellites readings. A reading is produced per fix, when the RMC sentence arrives
+// (or GGA for receivers that don't send RMC, with speed and course from VTG).
+#[derive(Default)]
+pub struct NmeaParser {
+    seen_rmc: bool,
+    altitude: Option<f64>,
+    hdop: Option<f64>,
+    // Standard deviation of the position from GST, preferred over HDOP
+    position_error: Option<f64>,
+    satellites_in_use: Option<i32>,
+    // Speed (m/s) and course from VTG, for fixes without RMC
+    speed: Option<f64>,
+    bearing: Option<f64>,
+    used_prns: Vec<i32>,
+    // GSA sentences of the previous fix are replaced by the first one of the next
+    used_prns_complete: bool,
+    // talker -> satellites in view, from complete GSV groups
+    satellites: BTreeMap<String, Vec<GpsSatellite>>,
+    gsv_partial: BTreeMap<String, Vec<GpsSatellite>>,
+}
+
+impl NmeaParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Lines that aren't valid NMEA sentences (e.g. gpsd JSON) are ignored
+    pub fn parse_line(&mut self, line: &str) -> Vec<SensorReading> {
+        let Some(fields) = Self::split_sentence(line.trim()) else {
+            return vec![];
+        };
+
+        // Garbled sentence ids are skipped, they can't be split into talker and type
+        if !fields[0].is_ascii() || fields[0].len() < 5 {
+            return vec![];
+        }
+
+        let (talker, sentence) = fields[0].split_at(fields[0].len() - 3);
+        match sentence {
+            "RMC" => self.parse_rmc(&fields),
+            "GGA" => self.parse_gga(&fields),
+            "GSA" => {
+                self.parse_gsa(&fields);
+                vec![]
+            }
+            "GSV" => {
+                self.parse_gsv(talker, &fields);
+                vec![]
+            }
+            "GST" => {
+                self.parse_gst(&fields);
+                vec![]
+            }
+            "VTG" => {
+                self.parse_vtg(&fields);
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    // Checks the checksum and returns the comma separated fields without the leading '$'
+    fn split_sentence(line: &str) -> Option<Vec<&str>> {
+        let body = line.strip_prefix('$').or_else(|| line.strip_prefix('!'))?;
+
+        let body = match body.split_once('*') {
+            Some((body, checksum)) => {
+                let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+                let actual = body.bytes().fold(0u8, |checksum, byte| checksum ^ byte);
+
+                if expected != actual {
+                    return None;
+                }
+
+                body
+            }
+            None => body,
+        };
+
+        Some(body.split(',').collect())
+    }
+
+    fn parse_rmc(&mut self, fields: &[&str]) -> Vec<SensorReading> {
+        self.seen_rmc = true;
+
+        // A = valid, V = receiver warning
+        if fields.get(2) != Some(&"A") {
+            return vec![];
+        }
+
+        let (Some(latitude), Some(longitude)) = (
+            parse_coordinate(field(fields, 3), field(fields, 4)),
+            parse_coordinate(field(fields, 5), field(fields, 6)),
+        ) else {
+            return vec![];
+        };
+
+        let mut location = LocationReading::new(latitude, longitude, self.accuracy());
+        if let Some(timestamp) = parse_timestamp(field(fields, 1), field(fields, 9)) {
+            location.timestamp = timestamp;
+        }
+        location.altitude = self.altitude;
+        location.speed = parse_f64(field(fields, 7)).map(|knots| knots * KNOTS_TO_MPS);
+        location.bearing = parse_f64(field(fields, 8));
+
+        self.used_prns_complete = true;
+
+        let mut readings = vec![SensorReading::Location(location)];
+        if let Some(satellites) = self.satellite_reading() {
+            readings.push(satellites);
+        }
+
+        readings
+    }
+
+    fn parse_gga(&mut self, fields: &[&str]) -> Vec<SensorReading> {
+        // 0 = no fix
+        let quality = parse_f64(field(fields, 6)).unwrap_or(0.0);
+
+        self.satellites_in_use = parse_f64(field(fields, 7)).map(|count| count as i32);
+        self.hdop = parse_f64(field(fields, 8));
+        self.altitude = if quality > 0.0 { parse_f64(field(fields, 9)) } else { None };
+
+        if self.seen_rmc || quality == 0.0 {
+            return vec![];
+        }
+
+        let (Some(latitude), Some(longitude)) = (
+            parse_coordinate(field(fields, 2), field(fields, 3)),
+            parse_coordinate(field(fields, 4), field(fields, 5)),
+        ) else {
+            return vec![];
+        };
+
+        let mut location = LocationReading::new(latitude, longitude, self.accuracy());
+        location.altitude = self.altitude;
+        location.speed = self.speed;
+        location.bearing = self.bearing;
+
+        self.used_prns_complete = true;
+
+        let mut readings = vec![SensorReading::Location(location)];
+        if let Some(satellites) = self.satellite_reading() {
+            readings.push(satellites);
+        }
+
+        readings
+    }
+
+    fn parse_gsa(&mut self, fields: &[&str]) {
+        if self.used_prns_complete {
+            self.used_prns.clear();
+            self.used_prns_complete = false;
+        }
+
+        for index in 3..15 {
+            if let Some(prn) = parse_f64(field(fields, index)) {
+                self.used_prns.push(prn as i32);
+            }
+        }
+
+        if self.hdop.is_none() {
+            self.hdop = parse_f64(field(fields, 16));
+        }
+    }
+
+    fn parse_gsv(&mut self, talker: &str, fields: &[&str]) {
+        let (Some(total), Some(number)) = (parse_f64(field(fields, 1)), parse_f64(field(fields, 2))) else {
+            return;
+        };
+
+        // Truncated sentence
+        if fields.len() < 4 {
+            return;
+        }
+
+        let partial = self.gsv_partial.entry(talker.to_owned()).or_default();
+        if number == 1.0 {
+            partial.clear();
+        }
+
+        // Groups of PRN, elevation, azimuth, SNR, NMEA 4.10 appends a signal id
+        for group in fields.get(4..).unwrap_or(&[]).chunks_exact(4) {
+            let Some(prn) = parse_f64(group[0]) else {
+                continue;
+            };
+
+            partial.push(GpsSatellite {
+                prn: prn as i32,
+                snr: parse_f64(group[3]).unwrap_or(0.0),
+                used_in_fix: false,
+                azimuth: parse_f64(group[2]).unwrap_or(0.0),
+                elevation: parse_f64(group[1]).unwrap_or(0.0),
+            });
+        }
+
+        if number >= total {
+            let satellites = std::mem::take(partial);
+            self.satellites.insert(talker.to_owned(), satellites);
+        }
+    }
+
+    fn parse_gst(&mut self, fields: &[&str]) {
+        if let (Some(latitude_error), Some(longitude_error)) = (parse_f64(field(fields, 6)), parse_f64(field(fields, 7))) {
+            self.position_error = Some((latitude_error * latitude_error + longitude_error * longitude_error).sqrt());
+        }
+    }
+
+    fn parse_vtg(&mut self, fields: &[&str]) {
+        // N = data not valid (NMEA 2.3 mode indicator)
+        if fields.get(9) == Some(&"N") {
+            self.speed = None;
+            self.bearing = None;
+            return;
+        }
+
+        self.bearing = parse_f64(field(fields, 1));
+        self.speed = parse_f64(field(fields, 5)).map(|knots| knots * KNOTS_TO_MPS);
+    }
+
+    fn accuracy(&self) -> f64 {
+        self.position_error
+            .or(self.hdop.map(|hdop| hdop * UERE_METERS))
+            .unwrap_or(0.0)
+    }
+
+    fn satellite_reading(&self) -> Option<SensorReading> {
+        let mut satellites: Vec<GpsSatellite> = self.satellites.values().flatten().cloned().collect();
+        if satellites.is_empty() {
+            return None;
+        }
+
+        for satellite in satellites.iter_mut() {
+            satellite.used_in_fix = self.used_prns.contains(&satellite.prn);
+        }
+
+        let in_use = self.satellites_in_use
+            .unwrap_or_else(|| satellites.iter().filter(|satellite| satellite.used_in_fix).count() as i32);
+
+        Some(SensorReading::GpsSatellites {
+            in_use,
+            in_view: satellites.len() as i32,
+            satellites,
+        })
+    }
+}
+
+fn field<'a>(fields: &[&'a str], index: usize) -> &'a str {
+    fields.get(index).copied().unwrap_or("")
+}
+
+fn parse_f64(value: &str) -> Option<f64> {
+    if value.is_empty() {
+        return None;
+    }
+
+    value.parse().ok()
+}
+
+// "ddmm.mmmm" / "dddmm.mmmm" with hemisphere N/S/E/W
+fn parse_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let value = parse_f64(value)?;
+
+    let degrees = (value / 100.0).trunc();
+    let coordinate = degrees + (value - degrees * 100.0) / 60.0;
+
+    match hemisphere {
+        "N" | "E" => Some(coordinate),
+        "S" | "W" => Some(-coordinate),
+        _ => None,
+    }
+}
+
+// "hhmmss.ss" and "ddmmyy" to milliseconds since the unix epoch
+fn parse_timestamp(time: &str, date: &str) -> Option<u64> {
+    if time.len() < 6 || date.len() != 6 {
+        return None;
+    }
+
+    let hours: u64 = time.get(0..2)?.parse().ok()?;
+    let minutes: u64 = time.get(2..4)?.parse().ok()?;
+    let seconds: f64 = time.get(4..)?.parse().ok()?;
+
+    let day: u32 = date.get(0..2)?.parse().ok()?;
+    let month: u32 = date.get(2..4)?.parse().ok()?;
+    let year: i64 = date.get(4..6)?.parse().ok()?;
+    let year = if year < 80 { 2000 + year } else { 1900 + year };
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+
+    Some(days as u64 * 86_400_000 + (hours * 3600 + minutes * 60) * 1000 + (seconds * 1000.0).round() as u64)
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Feeds Location and GpsSatellites readings from an NMEA source into a SensorService,
+// which has to provide `SensorType::Location` (and `SensorType::GPS` for satellites).
+pub struct NmeaLocationProvider {
+    parser: NmeaParser,
+    handle: SensorHandle,
+}
+
+impl NmeaLocationProvider {
+    pub fn new(handle: SensorHandle) -> Self {
+        Self {
+            parser: NmeaParser::new(),
+            handle,
+        }
+    }
+
+    // Returns the pushed readings, e.g. to pass them on to a DrivingStatusPolicy
+    pub fn feed(&mut self, line: &str) -> Vec<SensorReading> {
+        let readings = self.parser.parse_line(line);
+
+        if !readings.is_empty() {
+            self.handle.push_batch(readings.clone());
+        }
+
+        readings
+    }
+
+    // Reads sentences until the reader is exhausted, line noise (e.g. invalid UTF-8) is skipped
+    pub fn run<R: BufRead>(&mut self, mut reader: R) -> crate::error::Result<()> {
+        let mut line = vec![];
+
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(());
+            }
+
+            // Invalid bytes become U+FFFD, which fails the checksum or the sentence id check
+            self.feed(&String::from_utf8_lossy(&line));
+        }
+    }
+
+    // A serial device (configured to the right baud rate beforehand) or a recorded log
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> crate::error::Result<()> {
+        let file = std::fs::File::open(path)?;
+
+        self.run(BufReader::new(file))
+    }
+
+    // Asks gpsd (usually at localhost:2947) for raw NMEA, the JSON reports are skipped
+    pub fn run_gpsd<A: ToSocketAddrs>(&mut self, addr: A) -> crate::error::Result<()> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.write_all(b"?WATCH={\"enable\":true,\"nmea\":true}\n")?;
+
+        self.run(BufReader::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Appends the checksum to a sentence body
+    fn sentence(body: &str) -> String {
+        let checksum = body.bytes().fold(0u8, |checksum, byte| checksum ^ byte);
+
+        format!("${}*{:02X}", body, checksum)
+    }
+
+    fn first_location(readings: &[SensorReading]) -> &LocationReading {
+        match readings.first() {
+            Some(SensorReading::Location(location)) => location,
+            other => panic!("expected a location, got {:?}", other),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn rmc() {
+        let mut parser = NmeaParser::new();
+        let readings = parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+
+        assert_eq!(readings.len(), 1);
+        let location = first_location(&readings);
+        assert_close(location.latitude, 48.1173);
+        assert_close(location.longitude, 11.0 + 31.0 / 60.0);
+        assert_close(location.speed.unwrap(), 22.4 * KNOTS_TO_MPS);
+        assert_close(location.bearing.unwrap(), 84.4);
+        // 1994-03-23 12:35:19 UTC
+        assert_eq!(location.timestamp, 764_426_119_000);
+    }
+
+    #[test]
+    fn rmc_without_fix() {
+        let mut parser = NmeaParser::new();
+
+        assert!(parser.parse_line(&sentence("GPRMC,123519,V,,,,,,,230394,,")).is_empty());
+    }
+
+    #[test]
+    fn southern_and_western_hemisphere() {
+        let mut parser = NmeaParser::new();
+        let readings = parser.parse_line(&sentence("GNRMC,010203.00,A,3352.128,S,15112.558,W,0.0,,010124,,,A"));
+
+        let location = first_location(&readings);
+        assert_close(location.latitude, -(33.0 + 52.128 / 60.0));
+        assert_close(location.longitude, -(151.0 + 12.558 / 60.0));
+    }
+
+    #[test]
+    fn gga_without_rmc() {
+        let mut parser = NmeaParser::new();
+        let readings = parser.parse_line("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47");
+
+        let location = first_location(&readings);
+        assert_close(location.latitude, 48.1173);
+        assert_close(location.altitude.unwrap(), 545.4);
+        assert_close(location.accuracy, 0.9 * UERE_METERS);
+    }
+
+    #[test]
+    fn gga_is_merged_into_rmc() {
+        let mut parser = NmeaParser::new();
+        parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+
+        // Once RMC was seen GGA only contributes altitude and accuracy
+        assert!(parser.parse_line("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47").is_empty());
+
+        let readings = parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+        let location = first_location(&readings);
+        assert_close(location.altitude.unwrap(), 545.4);
+        assert_close(location.accuracy, 0.9 * UERE_METERS);
+    }
+
+    #[test]
+    fn gst_accuracy() {
+        let mut parser = NmeaParser::new();
+        parser.parse_line("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47");
+        parser.parse_line(&sentence("GPGST,123519,1.2,,,,3.0,4.0,5.0"));
+
+        let readings = parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+        assert_close(first_location(&readings).accuracy, 5.0);
+    }
+
+    #[test]
+    fn vtg() {
+        let mut parser = NmeaParser::new();
+        assert!(parser.parse_line(&sentence("GPVTG,054.7,T,034.4,M,005.5,N,010.2,K,A")).is_empty());
+
+        let readings = parser.parse_line("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47");
+        let location = first_location(&readings);
+        assert_close(location.speed.unwrap(), 5.5 * KNOTS_TO_MPS);
+        assert_close(location.bearing.unwrap(), 54.7);
+
+        parser.parse_line(&sentence("GPVTG,,T,,M,,N,,K,N"));
+
+        let readings = parser.parse_line("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47");
+        assert_eq!(first_location(&readings).speed, None);
+    }
+
+    #[test]
+    fn gsv() {
+        let mut parser = NmeaParser::new();
+        parser.parse_line(&sentence("GPGSA,A,3,01,02,,,,,,,,,,,1.8,0.9,1.5"));
+        parser.parse_line(&sentence("GPGSV,2,1,05,01,40,083,46,02,17,308,41,12,07,344,39,14,22,228,45"));
+        parser.parse_line(&sentence("GPGSV,2,2,05,15,55,120,"));
+        parser.parse_line(&sentence("GLGSV,1,1,01,65,30,045,38"));
+
+        let readings = parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+        assert_eq!(readings.len(), 2);
+
+        let SensorReading::GpsSatellites { in_use, in_view, satellites } = &readings[1] else {
+            panic!("expected satellites, got {:?}", readings[1]);
+        };
+
+        assert_eq!(*in_use, 2);
+        assert_eq!(*in_view, 6);
+
+        let satellite = |prn| satellites.iter().find(|satellite| satellite.prn == prn).unwrap();
+        assert_eq!(satellite(1), &GpsSatellite {
+            prn: 1,
+            snr: 46.0,
+            used_in_fix: true,
+            azimuth: 83.0,
+            elevation: 40.0,
+        });
+        assert!(satellite(2).used_in_fix);
+        assert!(!satellite(12).used_in_fix);
+        // No SNR while not tracked
+        assert_eq!(satellite(15).snr, 0.0);
+        assert_eq!(satellite(65).elevation, 30.0);
+    }
+
+    #[test]
+    fn incomplete_gsv_group_is_ignored() {
+        let mut parser = NmeaParser::new();
+        parser.parse_line(&sentence("GPGSV,2,1,05,01,40,083,46,02,17,308,41,12,07,344,39,14,22,228,45"));
+
+        let readings = parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+        assert_eq!(readings.len(), 1);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut parser = NmeaParser::new();
+
+        assert!(parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6B").is_empty());
+        assert!(parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*ZZ").is_empty());
+        assert!(parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*").is_empty());
+    }
+
+    #[test]
+    fn truncated_sentences() {
+        let mut parser = NmeaParser::new();
+        parser.parse_line(&sentence("GPGSV,1,1,01,01,40,083,46"));
+
+        for line in ["$GPGSV,1,1", "$GPGSV,1", "$GPGSV", "$GPRMC,123519,A,4807.038", "$GPGGA,123519,4807.038,N,01131", "$GPGST,1", "$GPVTG", "$", ""] {
+            assert!(parser.parse_line(line).is_empty(), "{}", line);
+        }
+
+        // The truncated GSV didn't clear the satellites of the last complete group
+        let readings = parser.parse_line("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+        assert_eq!(readings.len(), 2);
+    }
+
+    #[test]
+    fn non_ascii() {
+        let mut parser = NmeaParser::new();
+
+        for line in ["$GPÄMC,1,2", "$ÄÄ", "$GPRMÄ", "$GPRMC,123519,A,48ÄÄ.038,N,01131.000,E,,,230394,,*00", "$GPRMC*Ä", "\u{FFFD}\u{FFFD}$GPRMC"] {
+            assert!(parser.parse_line(line).is_empty(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn days_since_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(parse_timestamp("000000", "010100"), Some(946_684_800_000));
+        assert_eq!(parse_timestamp("000000.50", "010180"), Some(315_532_800_500));
+    }
+
+    #[test]
+    fn gpsd() {
+        use crate::service::sensor::testing::{last, sensor_service};
+        use crate::service::sensor::SensorType;
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut watch = vec![];
+            let mut byte = [0u8];
+            while byte[0] != b'\n' {
+                stream.read_exact(&mut byte).unwrap();
+                watch.push(byte[0]);
+            }
+
+            stream.write_all(b"{\"class\":\"VERSION\",\"release\":\"3.25\"}\r\n").unwrap();
+            stream.write_all(b"{\"class\":\"WATCH\",\"enable\":true,\"nmea\":true}\r\n").unwrap();
+            stream.write_all(b"$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A\r\n").unwrap();
+            stream.write_all(b"\xFF\xFE$GPRMC\r\n").unwrap();
+            // Closing the connection ends `run_gpsd`
+
+            String::from_utf8(watch).unwrap()
+        });
+
+        let (service, _) = sensor_service(&[SensorType::Location]);
+        let handle = service.handle();
+        let mut provider = NmeaLocationProvider::new(handle.clone());
+
+        provider.run_gpsd(addr).unwrap();
+
+        assert_eq!(server.join().unwrap(), "?WATCH={\"enable\":true,\"nmea\":true}\n");
+
+        let Some(SensorReading::Location(location)) = last(&handle, SensorType::Location) else {
+            panic!("no location relayed");
+        };
+        assert_close(location.latitude, 48.1173);
+        assert_eq!(location.timestamp, 764_426_119_000);
+    }
+
+    #[test]
+    fn gpsd_unreachable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let (service, _) = crate::service::sensor::testing::sensor_service(&[]);
+        let mut provider = NmeaLocationProvider::new(service.handle());
+
+        assert!(provider.run_gpsd(addr).is_err());
+    }
+}
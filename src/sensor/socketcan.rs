@@ -0,0 +1,791 @@
+use crate::service::sensor::{Gear, HeadlightState, SensorHandle, SensorReading, TurnIndicatorState};
+use protobuf::Enum;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteOrder {
+    // Intel, `start_bit` is the least significant bit
+    LittleEndian,
+    // Motorola, `start_bit` is the most significant bit in DBC numbering
+    BigEndian,
+}
+
+// Position and scaling of a signal like a DBC `SG_` line: physical = raw * factor + offset
+#[derive(Clone, Debug)]
+pub struct CanSignal {
+    // 11 bit ids, or 29 bit ids for anything above 0x7FF
+    pub can_id: u32,
+    pub start_bit: u32,
+    pub length: u32,
+    pub byte_order: ByteOrder,
+    pub signed: bool,
+    pub factor: f64,
+    pub offset: f64,
+    // Raw value -> physical value, replaces factor and offset when not empty (DBC `VAL_`)
+    pub values: Vec<(i64, f64)>,
+}
+
+impl CanSignal {
+    pub fn new(can_id: u32, start_bit: u32, length: u32) -> Self {
+        Self {
+            can_id,
+            start_bit,
+            length,
+            byte_order: ByteOrder::LittleEndian,
+            signed: false,
+            factor: 1.0,
+            offset: 0.0,
+            values: vec![],
+        }
+    }
+
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+
+        self
+    }
+
+    pub fn with_signed(mut self, signed: bool) -> Self {
+        self.signed = signed;
+
+        self
+    }
+
+    pub fn with_scale(mut self, factor: f64, offset: f64) -> Self {
+        self.factor = factor;
+        self.offset = offset;
+
+        self
+    }
+
+    pub fn with_values(mut self, values: &[(i64, f64)]) -> Self {
+        self.values = values.to_vec();
+
+        self
+    }
+
+    // None if the frame is too short or the raw value isn't in `values`
+    pub fn decode(&self, data: &[u8]) -> Option<f64> {
+        if self.length == 0 || self.length > 64 {
+            return None;
+        }
+
+        let bit = |position: u32| -> Option<u64> {
+            let byte = data.get(position as usize / 8)?;
+
+            Some((*byte as u64 >> (position % 8)) & 1)
+        };
+
+        let mut raw: u64 = 0;
+        match self.byte_order {
+            ByteOrder::LittleEndian => {
+                for index in (0..self.length).rev() {
+                    raw = (raw << 1) | bit(self.start_bit + index)?;
+                }
+            }
+            ByteOrder::BigEndian => {
+                let mut position = self.start_bit;
+                for index in 0..self.length {
+                    raw = (raw << 1) | bit(position)?;
+
+                    // Continue with the most significant bit of the next byte
+                    if index + 1 < self.length {
+                        position = if position.is_multiple_of(8) { position + 15 } else { position - 1 };
+                    }
+                }
+            }
+        }
+
+        let negative = self.signed && raw >> (self.length - 1) & 1 == 1;
+        let raw = if negative && self.length < 64 { raw | (u64::MAX << self.length) } else { raw };
+
+        if !self.values.is_empty() {
+            return self.values.iter().find(|(value, _)| *value == raw as i64).map(|(_, physical)| *physical);
+        }
+
+        // Unsigned 64 bit values don't fit into an i64
+        let raw = if negative { raw as i64 as f64 } else { raw as f64 };
+
+        Some(raw * self.factor + self.offset)
+    }
+}
+
+// What a signal feeds, booleans are true for any non-zero value and enums take the
+// sensors.proto numbers (e.g. `Gear::Park` = 101, `HeadlightState::Headlight2` = 2)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VehicleSignal {
+    // m/s
+    Speed,
+    CruiseEngaged,
+    Gear,
+    // Percent
+    FuelLevel,
+    // Kilometers
+    FuelRange,
+    LowFuel,
+    ParkingBrake,
+    HoodOpen,
+    BootOpen,
+    // Door number starting at 0
+    DoorOpen(usize),
+    Headlight,
+    TurnIndicator,
+    HazardLights,
+}
+
+#[derive(Clone, Debug)]
+pub struct SignalMapping {
+    pub signal: CanSignal,
+    pub target: VehicleSignal,
+}
+
+impl SignalMapping {
+    pub fn new(signal: CanSignal, target: VehicleSignal) -> Self {
+        Self { signal, target }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CanMappingConfig {
+    pub signals: Vec<SignalMapping>,
+}
+
+impl CanMappingConfig {
+    pub fn with_signal(mut self, signal: CanSignal, target: VehicleSignal) -> Self {
+        self.signals.push(SignalMapping::new(signal, target));
+
+        self
+    }
+
+    // Parses a mapping like
+    //
+    // [[signal]]
+    // target = "speed"
+    // can_id = 0x1A0
+    // start_bit = 0
+    // length = 16
+    // factor = 0.00277778   # 0.01 km/h to m/s
+    //
+    // [[signal]]
+    // target = "door_open"
+    // door = 1
+    // can_id = 0x3B0
+    // start_bit = 7
+    // length = 1
+    // byte_order = "big_endian"
+    //
+    // [[signal]]
+    // target = "gear"
+    // can_id = 0x2F0
+    // start_bit = 0
+    // length = 4
+    // values = [[0, 101], [1, 102], [2, 0], [3, 100]]
+    #[cfg(feature = "toml")]
+    pub fn from_toml(data: &str) -> crate::error::Result<Self> {
+        let file: toml_config::CanMappingFile = toml::from_str(data)
+            .map_err(|e| crate::error::Error::Config(e.to_string()))?;
+
+        file.try_into()
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> crate::error::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    fn can_ids(&self) -> Vec<u32> {
+        let mut can_ids = vec![];
+
+        for mapping in &self.signals {
+            if !can_ids.contains(&mapping.signal.can_id) {
+                can_ids.push(mapping.signal.can_id);
+            }
+        }
+
+        can_ids
+    }
+}
+
+#[derive(Default)]
+struct VehicleState {
+    speed: Option<f64>,
+    cruise_engaged: Option<bool>,
+    gear: Option<Gear>,
+    fuel_level: Option<i32>,
+    fuel_range: Option<i32>,
+    low_fuel: Option<bool>,
+    parking_brake: Option<bool>,
+    hood_open: bool,
+    boot_open: bool,
+    doors_open: Vec<bool>,
+    headlight: Option<HeadlightState>,
+    turn_indicator: Option<TurnIndicatorState>,
+    hazard_lights: Option<bool>,
+}
+
+// Decodes CAN frames with a `CanMappingConfig` and pushes the resulting readings to a
+// SensorService, which has to provide the matching sensor types (CarSpeed, Gear, FuelLevel,
+// Door, Light, PARKING_BRAKE). A reading is only pushed when its value changes.
+pub struct CanBridge {
+    config: CanMappingConfig,
+    handle: SensorHandle,
+    state: VehicleState,
+    last: Vec<SensorReading>,
+}
+
+impl CanBridge {
+    pub fn new(config: CanMappingConfig, handle: SensorHandle) -> Self {
+        let doors = config.signals.iter()
+            .filter_map(|mapping| match mapping.target {
+                VehicleSignal::DoorOpen(door) => Some(door + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        Self {
+            config,
+            handle,
+            state: VehicleState {
+                doors_open: vec![false; doors],
+                ..Default::default()
+            },
+            last: vec![],
+        }
+    }
+
+    // Binds to a CAN interface (e.g. "can0" or "vcan0"), receiving only the mapped ids
+    pub fn open(&self, interface: &str) -> crate::error::Result<CanSocket> {
+        CanSocket::open(interface, &self.config.can_ids())
+    }
+
+    // Reads frames until the interface goes down (Error::IoDisconnected) or fails
+    pub fn run(&mut self, socket: &CanSocket) -> crate::error::Result<()> {
+        loop {
+            let (can_id, data) = socket.read_frame()?;
+            self.handle_frame(can_id, &data);
+        }
+    }
+
+    // Returns the pushed readings, e.g. to pass them on to a DrivingStatusPolicy
+    pub fn handle_frame(&mut self, can_id: u32, data: &[u8]) -> Vec<SensorReading> {
+        let mut touched = vec![];
+
+        for mapping in self.config.signals.iter().filter(|mapping| mapping.signal.can_id == can_id) {
+            let Some(value) = mapping.signal.decode(data) else {
+                continue;
+            };
+
+            Self::apply(&mut self.state, mapping.target, value);
+
+            let group = Self::group(mapping.target);
+            if !touched.contains(&group) {
+                touched.push(group);
+            }
+        }
+
+        let mut readings = vec![];
+        for group in touched {
+            let Some(reading) = self.reading(group) else {
+                continue;
+            };
+
+            if self.last.contains(&reading) {
+                continue;
+            }
+
+            self.last.retain(|last| last.sensor_type() != reading.sensor_type());
+            self.last.push(reading.clone());
+            readings.push(reading);
+        }
+
+        if !readings.is_empty() {
+            self.handle.push_batch(readings.clone());
+        }
+
+        readings
+    }
+
+    fn apply(state: &mut VehicleState, target: VehicleSignal, value: f64) {
+        let flag = value != 0.0;
+        let number = value.round() as i32;
+
+        match target {
+            VehicleSignal::Speed => state.speed = Some(value),
+            VehicleSignal::CruiseEngaged => state.cruise_engaged = Some(flag),
+            VehicleSignal::Gear => state.gear = Gear::from_i32(number),
+            VehicleSignal::FuelLevel => state.fuel_level = Some(number.clamp(0, 100)),
+            VehicleSignal::FuelRange => state.fuel_range = Some(number),
+            VehicleSignal::LowFuel => state.low_fuel = Some(flag),
+            VehicleSignal::ParkingBrake => state.parking_brake = Some(flag),
+            VehicleSignal::HoodOpen => state.hood_open = flag,
+            VehicleSignal::BootOpen => state.boot_open = flag,
+            VehicleSignal::DoorOpen(door) => {
+                if let Some(open) = state.doors_open.get_mut(door) {
+                    *open = flag;
+                }
+            }
+            VehicleSignal::Headlight => state.headlight = HeadlightState::from_i32(number),
+            VehicleSignal::TurnIndicator => state.turn_indicator = TurnIndicatorState::from_i32(number),
+            VehicleSignal::HazardLights => state.hazard_lights = Some(flag),
+        }
+    }
+
+    // Signals that end up in the same reading share a group
+    fn group(target: VehicleSignal) -> VehicleSignal {
+        match target {
+            VehicleSignal::CruiseEngaged => VehicleSignal::Speed,
+            VehicleSignal::FuelRange | VehicleSignal::LowFuel => VehicleSignal::FuelLevel,
+            VehicleSignal::BootOpen | VehicleSignal::DoorOpen(_) => VehicleSignal::HoodOpen,
+            VehicleSignal::TurnIndicator | VehicleSignal::HazardLights => VehicleSignal::Headlight,
+            target => target,
+        }
+    }
+
+    fn reading(&self, group: VehicleSignal) -> Option<SensorReading> {
+        let state = &self.state;
+
+        match group {
+            VehicleSignal::Speed => Some(SensorReading::Speed {
+                speed: state.speed?,
+                cruise_engaged: state.cruise_engaged,
+                cruise_set_speed: None,
+            }),
+            VehicleSignal::Gear => state.gear.map(SensorReading::Gear),
+            VehicleSignal::FuelLevel => Some(SensorReading::Fuel {
+                level: state.fuel_level?,
+                range: state.fuel_range,
+                low_fuel: state.low_fuel,
+            }),
+            VehicleSignal::ParkingBrake => state.parking_brake.map(SensorReading::ParkingBrake),
+            VehicleSignal::HoodOpen => Some(SensorReading::Door {
+                hood_open: state.hood_open,
+                boot_open: state.boot_open,
+                doors_open: state.doors_open.clone(),
+            }),
+            VehicleSignal::Headlight => Some(SensorReading::Light {
+                headlight: state.headlight,
+                turn_indicator: state.turn_indicator,
+                hazard_light_on: state.hazard_lights,
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Raw SocketCAN socket for classic CAN frames
+pub struct CanSocket {
+    fd: OwnedFd,
+}
+
+impl CanSocket {
+    // Only frames with one of `can_ids` are received, all frames if empty
+    pub fn open(interface: &str, can_ids: &[u32]) -> crate::error::Result<Self> {
+        let name = CString::new(interface).map_err(|_| crate::error::Error::Config(format!("invalid interface {}", interface)))?;
+
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let fd = unsafe { libc::socket(libc::PF_CAN, libc::SOCK_RAW, libc::CAN_RAW) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let socket = Self { fd: unsafe { OwnedFd::from_raw_fd(fd) } };
+
+        if !can_ids.is_empty() {
+            let filters: Vec<libc::can_filter> = can_ids.iter()
+                .map(|can_id| {
+                    if *can_id > libc::CAN_SFF_MASK {
+                        libc::can_filter {
+                            can_id: can_id | libc::CAN_EFF_FLAG,
+                            can_mask: libc::CAN_EFF_MASK | libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG,
+                        }
+                    } else {
+                        libc::can_filter {
+                            can_id: *can_id,
+                            can_mask: libc::CAN_SFF_MASK | libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG,
+                        }
+                    }
+                })
+                .collect();
+
+            let result = unsafe {
+                libc::setsockopt(
+                    socket.fd.as_raw_fd(),
+                    libc::SOL_CAN_RAW,
+                    libc::CAN_RAW_FILTER,
+                    filters.as_ptr() as *const libc::c_void,
+                    std::mem::size_of_val(filters.as_slice()) as libc::socklen_t,
+                )
+            };
+
+            if result < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+
+        let mut address: libc::sockaddr_can = unsafe { std::mem::zeroed() };
+        address.can_family = libc::AF_CAN as libc::sa_family_t;
+        address.can_ifindex = index as libc::c_int;
+
+        let result = unsafe {
+            libc::bind(
+                socket.fd.as_raw_fd(),
+                &address as *const libc::sockaddr_can as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            )
+        };
+
+        if result < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        println!("CanSocket: Bound to {}", interface);
+
+        Ok(socket)
+    }
+
+    // Blocks for the next data frame, remote and error frames are skipped
+    pub fn read_frame(&self) -> crate::error::Result<(u32, Vec<u8>)> {
+        loop {
+            let mut frame: libc::can_frame = unsafe { std::mem::zeroed() };
+
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    &mut frame as *mut libc::can_frame as *mut libc::c_void,
+                    std::mem::size_of::<libc::can_frame>(),
+                )
+            };
+
+            if read < 0 {
+                let e = std::io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    Some(libc::ENETDOWN) | Some(libc::ENODEV) => return Err(crate::error::Error::IoDisconnected),
+                    _ => return Err(e.into()),
+                }
+            }
+
+            if (read as usize) < std::mem::size_of::<libc::can_frame>()
+                || frame.can_id & (libc::CAN_RTR_FLAG | libc::CAN_ERR_FLAG) != 0 {
+                continue;
+            }
+
+            let can_id = if frame.can_id & libc::CAN_EFF_FLAG != 0 {
+                frame.can_id & libc::CAN_EFF_MASK
+            } else {
+                frame.can_id & libc::CAN_SFF_MASK
+            };
+
+            let length = (frame.can_dlc as usize).min(frame.data.len());
+
+            return Ok((can_id, frame.data[..length].to_vec()));
+        }
+    }
+
+    // E.g. to replay recorded traffic on a vcan interface
+    pub fn send_frame(&self, can_id: u32, data: &[u8]) -> crate::error::Result<()> {
+        let mut frame: libc::can_frame = unsafe { std::mem::zeroed() };
+
+        frame.can_id = if can_id > libc::CAN_SFF_MASK { can_id | libc::CAN_EFF_FLAG } else { can_id };
+        frame.can_dlc = data.len().min(frame.data.len()) as u8;
+        frame.data[..frame.can_dlc as usize].copy_from_slice(&data[..frame.can_dlc as usize]);
+
+        let written = unsafe {
+            libc::write(
+                self.fd.as_raw_fd(),
+                &frame as *const libc::can_frame as *const libc::c_void,
+                std::mem::size_of::<libc::can_frame>(),
+            )
+        };
+
+        if written < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "toml")]
+mod toml_config {
+    use super::{ByteOrder, CanMappingConfig, CanSignal, SignalMapping, VehicleSignal};
+    use crate::error::Error;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub(super) struct CanMappingFile {
+        #[serde(default)]
+        signal: Vec<SignalEntry>,
+    }
+
+    #[derive(Deserialize)]
+    struct SignalEntry {
+        target: String,
+        door: Option<usize>,
+        can_id: u32,
+        start_bit: u32,
+        length: u32,
+        byte_order: Option<String>,
+        #[serde(default)]
+        signed: bool,
+        factor: Option<f64>,
+        #[serde(default)]
+        offset: f64,
+        #[serde(default)]
+        values: Vec<(i64, f64)>,
+    }
+
+    fn target(name: &str, door: Option<usize>) -> crate::error::Result<VehicleSignal> {
+        Ok(match name {
+            "speed" => VehicleSignal::Speed,
+            "cruise_engaged" => VehicleSignal::CruiseEngaged,
+            "gear" => VehicleSignal::Gear,
+            "fuel_level" => VehicleSignal::FuelLevel,
+            "fuel_range" => VehicleSignal::FuelRange,
+            "low_fuel" => VehicleSignal::LowFuel,
+            "parking_brake" => VehicleSignal::ParkingBrake,
+            "hood_open" => VehicleSignal::HoodOpen,
+            "boot_open" => VehicleSignal::BootOpen,
+            "door_open" => VehicleSignal::DoorOpen(door.ok_or_else(|| Error::Config("door_open needs a door".to_owned()))?),
+            "headlight" => VehicleSignal::Headlight,
+            "turn_indicator" => VehicleSignal::TurnIndicator,
+            "hazard_lights" => VehicleSignal::HazardLights,
+            _ => return Err(Error::Config(format!("unknown target {}", name))),
+        })
+    }
+
+    fn byte_order(name: &str) -> crate::error::Result<ByteOrder> {
+        match name {
+            "little_endian" | "intel" => Ok(ByteOrder::LittleEndian),
+            "big_endian" | "motorola" => Ok(ByteOrder::BigEndian),
+            _ => Err(Error::Config(format!("unknown byte order {}", name))),
+        }
+    }
+
+    impl TryFrom<CanMappingFile> for CanMappingConfig {
+        type Error = Error;
+
+        fn try_from(file: CanMappingFile) -> crate::error::Result<Self> {
+            let mut signals = vec![];
+            for entry in file.signal {
+                signals.push(SignalMapping {
+                    signal: CanSignal {
+                        can_id: entry.can_id,
+                        start_bit: entry.start_bit,
+                        length: entry.length,
+                        byte_order: match entry.byte_order {
+                            Some(name) => byte_order(&name)?,
+                            None => ByteOrder::LittleEndian,
+                        },
+                        signed: entry.signed,
+                        factor: entry.factor.unwrap_or(1.0),
+                        offset: entry.offset,
+                        values: entry.values,
+                    },
+                    target: target(&entry.target, entry.door)?,
+                });
+            }
+
+            Ok(CanMappingConfig { signals })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::sensor::testing::last;
+    use crate::service::sensor::SensorType;
+
+    fn intel(start_bit: u32, length: u32) -> CanSignal {
+        CanSignal::new(0x100, start_bit, length)
+    }
+
+    fn motorola(start_bit: u32, length: u32) -> CanSignal {
+        CanSignal::new(0x100, start_bit, length).with_byte_order(ByteOrder::BigEndian)
+    }
+
+    #[test]
+    fn little_endian() {
+        assert_eq!(intel(8, 8).decode(&[0x00, 0xAB]), Some(171.0));
+        assert_eq!(intel(0, 1).decode(&[0x01]), Some(1.0));
+        assert_eq!(intel(7, 1).decode(&[0x7F]), Some(0.0));
+        assert_eq!(intel(16, 16).decode(&[0x00, 0x00, 0x34, 0x12]), Some(0x1234 as f64));
+    }
+
+    #[test]
+    fn little_endian_across_bytes() {
+        // Bits 4..16
+        assert_eq!(intel(4, 12).decode(&[0x30, 0x12]), Some(0x123 as f64));
+        // Bits 6..9
+        assert_eq!(intel(6, 3).decode(&[0b1100_0000, 0b0000_0001]), Some(0b111 as f64));
+        assert_eq!(intel(6, 3).decode(&[0b0100_0000, 0b0000_0000]), Some(0b001 as f64));
+    }
+
+    #[test]
+    fn big_endian() {
+        assert_eq!(motorola(7, 8).decode(&[0xAB]), Some(171.0));
+        assert_eq!(motorola(7, 16).decode(&[0x12, 0x34]), Some(0x1234 as f64));
+        // Bits 13..8
+        assert_eq!(motorola(13, 6).decode(&[0x00, 0b0010_1101]), Some(45.0));
+    }
+
+    #[test]
+    fn big_endian_across_bytes() {
+        // Low nibble of byte 0, then all of byte 1
+        assert_eq!(motorola(3, 12).decode(&[0x0A, 0xBC]), Some(0xABC as f64));
+        // Bits 2..0 of byte 0, then bits 7..5 of byte 1
+        assert_eq!(motorola(2, 6).decode(&[0b0000_0101, 0b1100_0000]), Some(0b101110 as f64));
+        assert_eq!(motorola(39, 24).decode(&[0, 0, 0, 0, 0x01, 0x02, 0x03]), Some(0x010203 as f64));
+    }
+
+    #[test]
+    fn full_frame() {
+        let data = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF];
+
+        assert_eq!(intel(0, 64).decode(&data), Some(0xEFCD_AB89_6745_2301u64 as f64));
+        assert_eq!(motorola(7, 64).decode(&data), Some(0x0123_4567_89AB_CDEFu64 as f64));
+        assert_eq!(intel(0, 64).with_signed(true).decode(&[0xFF; 8]), Some(-1.0));
+    }
+
+    #[test]
+    fn signed() {
+        assert_eq!(intel(0, 8).with_signed(true).decode(&[0xFF]), Some(-1.0));
+        assert_eq!(intel(0, 8).with_signed(true).decode(&[0x7F]), Some(127.0));
+        assert_eq!(intel(0, 8).decode(&[0xFF]), Some(255.0));
+        assert_eq!(intel(4, 12).with_signed(true).decode(&[0x00, 0x80]), Some(-2048.0));
+        assert_eq!(intel(4, 12).with_signed(true).decode(&[0xF0, 0x7F]), Some(2047.0));
+        assert_eq!(motorola(7, 16).with_signed(true).decode(&[0xFF, 0x38]), Some(-200.0));
+        assert_eq!(motorola(3, 12).with_signed(true).decode(&[0x0F, 0xFE]), Some(-2.0));
+        assert_eq!(intel(3, 1).with_signed(true).decode(&[0x08]), Some(-1.0));
+    }
+
+    #[test]
+    fn scale_and_offset() {
+        assert_eq!(intel(0, 8).with_scale(0.5, -40.0).decode(&[100]), Some(10.0));
+
+        let temperature = motorola(7, 16).with_signed(true).with_scale(0.1, 0.0);
+        let value = temperature.decode(&[0xFF, 0x38]).unwrap();
+        assert!((value + 20.0).abs() < 1e-9, "{}", value);
+    }
+
+    #[test]
+    fn value_table() {
+        let gear = intel(0, 4).with_values(&[(0, 101.0), (1, 102.0), (2, 100.0)]);
+
+        assert_eq!(gear.decode(&[0x02]), Some(100.0));
+        assert_eq!(gear.decode(&[0xF1]), Some(102.0));
+        assert_eq!(gear.decode(&[0x05]), None);
+
+        let signed = intel(0, 2).with_signed(true).with_values(&[(-1, 1.0)]);
+        assert_eq!(signed.decode(&[0x03]), Some(1.0));
+    }
+
+    #[test]
+    fn short_frame() {
+        assert_eq!(intel(8, 8).decode(&[0xFF]), None);
+        assert_eq!(intel(4, 12).decode(&[0xFF]), None);
+        assert_eq!(motorola(3, 12).decode(&[0xFF]), None);
+        assert_eq!(intel(0, 8).decode(&[]), None);
+    }
+
+    #[test]
+    fn invalid_length() {
+        assert_eq!(intel(0, 0).decode(&[0xFF]), None);
+        assert_eq!(intel(0, 65).decode(&[0xFF; 9]), None);
+    }
+
+    fn bridge() -> (CanBridge, SensorHandle) {
+        let (service, _) = crate::service::sensor::testing::sensor_service(&[
+            SensorType::CarSpeed,
+            SensorType::Gear,
+            SensorType::Door,
+        ]);
+        let handle = service.handle();
+
+        let config = CanMappingConfig::default()
+            // 0.01 km/h
+            .with_signal(CanSignal::new(0x1A0, 0, 16).with_scale(0.01 / 3.6, 0.0), VehicleSignal::Speed)
+            .with_signal(CanSignal::new(0x1A0, 16, 1), VehicleSignal::CruiseEngaged)
+            .with_signal(CanSignal::new(0x18FEF100, 0, 4).with_values(&[(0, 101.0), (1, 102.0), (2, 100.0)]), VehicleSignal::Gear)
+            .with_signal(CanSignal::new(0x3B0, 0, 1), VehicleSignal::DoorOpen(1))
+            .with_signal(CanSignal::new(0x3B0, 1, 1), VehicleSignal::BootOpen);
+
+        (CanBridge::new(config, handle.clone()), handle)
+    }
+
+    fn speed(readings: &[SensorReading]) -> Option<(f64, Option<bool>)> {
+        readings.iter().find_map(|reading| match reading {
+            SensorReading::Speed { speed, cruise_engaged, .. } => Some((*speed, *cruise_engaged)),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn bridge_readings() {
+        let (mut bridge, handle) = bridge();
+
+        // 36 km/h with cruise control, both signals end up in one reading
+        let readings = bridge.handle_frame(0x1A0, &[0x10, 0x0E, 0x01]);
+        assert_eq!(readings.len(), 1);
+        let (speed, cruise_engaged) = speed(&readings).unwrap();
+        assert!((speed - 10.0).abs() < 1e-9, "{}", speed);
+        assert_eq!(cruise_engaged, Some(true));
+        assert_eq!(last(&handle, SensorType::CarSpeed), Some(readings[0].clone()));
+
+        // Unchanged values aren't pushed again
+        assert!(bridge.handle_frame(0x1A0, &[0x10, 0x0E, 0x01]).is_empty());
+
+        assert_eq!(bridge.handle_frame(0x18FEF100, &[0x00]), [SensorReading::Gear(Gear::Park)]);
+        // Unmapped value
+        assert!(bridge.handle_frame(0x18FEF100, &[0x0F]).is_empty());
+        assert!(bridge.handle_frame(0x123, &[0xFF]).is_empty());
+
+        assert_eq!(bridge.handle_frame(0x3B0, &[0b01]), [SensorReading::Door { hood_open: false, boot_open: false, doors_open: vec![false, true] }]);
+        assert_eq!(bridge.handle_frame(0x3B0, &[0b10]), [SensorReading::Door { hood_open: false, boot_open: true, doors_open: vec![false, false] }]);
+    }
+
+    // Set up with `ip link add dev vcan0 type vcan && ip link set up vcan0`
+    #[test]
+    #[ignore = "needs a vcan0 interface"]
+    fn vcan() {
+        let (mut bridge, handle) = bridge();
+        let socket = bridge.open("vcan0").unwrap();
+        let sender = CanSocket::open("vcan0", &[]).unwrap();
+
+        let (frame_sender, frames) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            while let Ok(frame) = socket.read_frame() {
+                if frame_sender.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Filtered out by the bridge socket
+        sender.send_frame(0x123, &[0xFF]).unwrap();
+        sender.send_frame(0x1A0, &[0x10, 0x0E, 0x00]).unwrap();
+        sender.send_frame(0x18FEF100, &[0x02]).unwrap();
+
+        let timeout = std::time::Duration::from_secs(5);
+
+        let (can_id, data) = frames.recv_timeout(timeout).unwrap();
+        assert_eq!((can_id, data.as_slice()), (0x1A0, &[0x10, 0x0E, 0x00][..]));
+        let readings = bridge.handle_frame(can_id, &data);
+        let (speed, cruise_engaged) = speed(&readings).unwrap();
+        assert!((speed - 10.0).abs() < 1e-9, "{}", speed);
+        assert_eq!(cruise_engaged, Some(false));
+
+        // Extended ids come back without the flag
+        let (can_id, data) = frames.recv_timeout(timeout).unwrap();
+        assert_eq!((can_id, data.as_slice()), (0x18FEF100, &[0x02][..]));
+        assert_eq!(bridge.handle_frame(can_id, &data), [SensorReading::Gear(Gear::Drive)]);
+        assert_eq!(last(&handle, SensorType::Gear), Some(SensorReading::Gear(Gear::Drive)));
+    }
+}
@@ -1,33 +1,180 @@
+#[cfg(feature = "aac")]
+use crate::codec::aac::AacDecoder;
 use crate::connection::ConnectionContext;
 use crate::message::{MediaMessageType, Message};
 use crate::protobuf::control::service::MediaSinkService;
 use crate::protobuf::media;
 use crate::protobuf::media::config::ConfigStatus;
-use crate::protobuf::media::{AudioConfiguration, AudioStreamType, MediaCodecType, MediaSetupRequest};
+use crate::protobuf::media::{AudioConfiguration, MediaSetupRequest};
 use crate::service::Service;
 use protobuf::Message as ProtoMessage;
 use std::sync::Arc;
 
+pub use crate::protobuf::media::{AudioStreamType, MediaCodecType};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioConfig {
+    pub sample_rate: u32,
+    pub number_of_bits: u32,
+    pub number_of_channels: u32,
+}
+
+impl AudioConfig {
+    pub fn new(sample_rate: u32, number_of_bits: u32, number_of_channels: u32) -> Self {
+        Self {
+            sample_rate,
+            number_of_bits,
+            number_of_channels,
+        }
+    }
+
+    pub(crate) fn to_protobuf(self) -> AudioConfiguration {
+        let mut audio_config = AudioConfiguration::new();
+        audio_config.sample_rate = Some(self.sample_rate);
+        audio_config.number_of_bits = Some(self.number_of_bits);
+        audio_config.number_of_channels = Some(self.number_of_channels);
+
+        audio_config
+    }
+}
+
+pub struct AudioServiceConfig {
+    pub stream_type: AudioStreamType,
+    pub codec: MediaCodecType,
+    // In order of preference, the phone picks one of them when starting a stream
+    pub configs: Vec<AudioConfig>,
+    // Decode AAC streams and hand PCM to the sink instead of the encoded frames
+    #[cfg(feature = "aac")]
+    pub decode: bool,
+}
+
+impl AudioServiceConfig {
+    pub fn new(stream_type: AudioStreamType, codec: MediaCodecType, configs: Vec<AudioConfig>) -> Self {
+        Self {
+            stream_type,
+            codec,
+            configs,
+            #[cfg(feature = "aac")]
+            decode: false,
+        }
+    }
+
+    // Guidance prompts and assistant responses
+    pub fn speech() -> Self {
+        Self::new(AudioStreamType::Speech, MediaCodecType::MediaCodecAudioPCM, vec![AudioConfig::new(16000, 16, 1)])
+    }
+
+    pub fn system() -> Self {
+        Self::new(AudioStreamType::System, MediaCodecType::MediaCodecAudioPCM, vec![AudioConfig::new(16000, 16, 1)])
+    }
+
+    pub fn media() -> Self {
+        Self::new(AudioStreamType::Media, MediaCodecType::MediaCodecAudioPCM, vec![AudioConfig::new(48000, 16, 2)])
+    }
+
+    // Needs considerably less bandwidth than PCM
+    pub fn media_aac() -> Self {
+        Self::new(AudioStreamType::Media, MediaCodecType::MediaCodecAudioAACLC, vec![AudioConfig::new(48000, 16, 2)])
+    }
+
+    #[cfg(feature = "aac")]
+    pub fn with_decoding(mut self) -> Self {
+        self.decode = true;
+
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AudioStreamInfo {
+    pub stream_type: AudioStreamType,
+    pub codec: MediaCodecType,
+    pub sample_rate: u32,
+    pub number_of_bits: u32,
+    pub number_of_channels: u32,
+}
+
+// Receives the audio of an AudioService, for PCM `data` is interleaved little endian samples,
+// otherwise the encoded frames of `info.codec`. `timestamp` is in microseconds (if the phone sent one).
+pub trait AudioSink: Send {
+    fn on_start(&mut self, _info: &AudioStreamInfo) {}
+
+    // Only called for encoded streams, e.g. the AudioSpecificConfig of AAC
+    fn on_codec_config(&mut self, _info: &AudioStreamInfo, _data: &[u8]) {}
+
+    fn on_data(&mut self, info: &AudioStreamInfo, timestamp: Option<u64>, data: &[u8]);
+
+    fn on_stop(&mut self, _info: &AudioStreamInfo) {}
+}
+
 pub struct AudioService {
+    config: AudioServiceConfig,
+    session_id: Option<i32>,
+    configuration_index: usize,
+    sink: Option<Box<dyn AudioSink>>,
+    #[cfg(feature = "aac")]
+    decoder: Option<AacDecoder>,
     context: Arc<ConnectionContext>,
 }
 
 impl AudioService {
-    pub fn new(context: Arc<ConnectionContext>) -> Self {
+    pub fn new(config: AudioServiceConfig, context: Arc<ConnectionContext>) -> Self {
+        assert!(!config.configs.is_empty(), "AudioService needs at least one AudioConfig");
+
         Self {
+            config,
+            session_id: None,
+            configuration_index: 0,
+            sink: None,
+            #[cfg(feature = "aac")]
+            decoder: None,
             context,
         }
     }
 
+    pub fn with_sink<A: AudioSink + 'static>(mut self, sink: A) -> Self {
+        self.sink = Some(Box::new(sink));
+
+        self
+    }
+
+    fn stream_info(&self) -> AudioStreamInfo {
+        let config = self.config.configs[self.configuration_index];
+
+        // What the decoder actually produces, the advertised config until the first frame
+        #[cfg(feature = "aac")]
+        if let Some(decoder) = self.decoder.as_ref() {
+            return AudioStreamInfo {
+                stream_type: self.config.stream_type,
+                codec: MediaCodecType::MediaCodecAudioPCM,
+                sample_rate: decoder.sample_rate().unwrap_or(config.sample_rate),
+                number_of_bits: 16,
+                number_of_channels: decoder.number_of_channels().unwrap_or(config.number_of_channels),
+            };
+        }
+
+        AudioStreamInfo {
+            stream_type: self.config.stream_type,
+            codec: self.config.codec,
+            sample_rate: config.sample_rate,
+            number_of_bits: config.number_of_bits,
+            number_of_channels: config.number_of_channels,
+        }
+    }
+
     pub fn handle_media_setup_request(&mut self, message: Message) {
         let data = MediaSetupRequest::parse_from_bytes(message.data.as_slice()).unwrap();
-        let type_ = data.type_;
 
-        if let Some(type_) = type_ {
+        if let Some(type_) = data.type_ {
+            if type_.enum_value() != Ok(self.config.codec) {
+                println!("AudioChannel {}: Setup for {:?}, but {:?} was advertised", message.channel, type_, self.config.codec);
+            }
+
             let mut config = media::Config::new();
             config.set_status(ConfigStatus::HeadUnit);
             config.set_max_unacked(1);
-            config.configuration_indices.push(0u32);
+            // All configs are acceptable, in order of preference
+            config.configuration_indices = (0..self.config.configs.len() as u32).collect();
 
             let mut commands = self.context.commands().lock().unwrap();
             commands.send_message(Message::new_with_protobuf_message(
@@ -38,6 +185,126 @@ impl AudioService {
             ), true);
         }
     }
+
+    pub fn handle_media_start_request(&mut self, message: Message) {
+        let req = media::Start::parse_from_bytes(message.data.as_slice()).unwrap();
+
+        self.session_id = req.session_id;
+
+        let configuration_index = req.configuration_index.unwrap_or(0) as usize;
+        if configuration_index < self.config.configs.len() {
+            self.configuration_index = configuration_index;
+        } else {
+            println!("AudioChannel {}: Unknown configuration index {}", message.channel, configuration_index);
+            self.configuration_index = 0;
+        }
+
+        #[cfg(feature = "aac")]
+        if self.config.decode {
+            let config = self.config.configs[self.configuration_index];
+
+            self.decoder = match AacDecoder::new(self.config.codec, config) {
+                Ok(decoder) => Some(decoder),
+                Err(e) => {
+                    println!("AudioChannel {}: Can't decode stream: {:?}", message.channel, e);
+                    None
+                }
+            };
+        }
+
+        let info = self.stream_info();
+        if let Some(sink) = self.sink.as_mut() {
+            sink.on_start(&info);
+        }
+    }
+
+    pub fn handle_media_stop_request(&mut self, _message: Message) {
+        self.session_id = None;
+
+        let info = self.stream_info();
+        if let Some(sink) = self.sink.as_mut() {
+            sink.on_stop(&info);
+        }
+    }
+
+    pub fn handle_media_data(&mut self, message: Message, with_timestamp: bool) {
+        let (timestamp, data) = if with_timestamp {
+            if message.data.len() < 8 {
+                println!("AudioChannel {}: Dropping media data without a timestamp ({} bytes)", message.channel, message.data.len());
+                self.send_media_ack(message.channel);
+                return;
+            }
+
+            let timestamp = u64::from_be_bytes(message.data[..8].try_into().unwrap());
+            (Some(timestamp), &message.data[8..])
+        } else {
+            (None, &message.data[..])
+        };
+
+        #[cfg(feature = "aac")]
+        if let Some(decoder) = self.decoder.as_mut() {
+            match decoder.decode(data) {
+                Ok(pcm) => {
+                    let info = self.stream_info();
+                    if let Some(sink) = self.sink.as_mut() {
+                        sink.on_data(&info, timestamp, &pcm);
+                    }
+                }
+                Err(e) => println!("AudioChannel {}: Decoding failed: {:?}", message.channel, e),
+            }
+
+            self.send_media_ack(message.channel);
+            return;
+        }
+
+        let info = self.stream_info();
+        if let Some(sink) = self.sink.as_mut() {
+            sink.on_data(&info, timestamp, data);
+        }
+
+        self.send_media_ack(message.channel);
+    }
+
+    pub fn handle_codec_data(&mut self, message: Message) {
+        if self.config.codec == MediaCodecType::MediaCodecAudioPCM {
+            // PCM has no codec config, these are samples without a timestamp
+            self.handle_media_data(message, false);
+            return;
+        }
+
+        #[cfg(feature = "aac")]
+        if let Some(decoder) = self.decoder.as_mut() {
+            if let Err(e) = decoder.configure(&message.data) {
+                println!("AudioChannel {}: Invalid codec config: {:?}", message.channel, e);
+            }
+
+            self.send_media_ack(message.channel);
+            return;
+        }
+
+        let info = self.stream_info();
+        if let Some(sink) = self.sink.as_mut() {
+            sink.on_codec_config(&info, &message.data);
+        }
+
+        self.send_media_ack(message.channel);
+    }
+
+    pub fn send_media_ack(&mut self, channel: u8) {
+        if let Some(session_id) = self.session_id {
+            let mut ack = media::Ack::new();
+            ack.set_session_id(session_id);
+            ack.set_ack(1);
+
+            let mut commands = self.context.commands().lock().unwrap();
+            commands.send_message(Message::new_with_protobuf_message(
+                channel,
+                false,
+                ack,
+                MediaMessageType::Ack as u16
+            ), true);
+        }
+    }
 }
 
 impl Service for AudioService {
@@ -45,37 +312,121 @@ impl Service for AudioService {
         let mut service = crate::protobuf::control::Service::new();
         service.id = Some(channel_id as u32);
 
-        let (audio_type, number_of_channels, sample_rate) = match channel_id {
-            4 => (AudioStreamType::Speech, 1, 16000),
-            5 => (AudioStreamType::System, 1, 16000),
-            6 => (AudioStreamType::Media, 2, 48000),
-            _ => todo!("Error? Channel {} not supported", channel_id)
-        };
-        
         let mut media_sink = MediaSinkService::new();
-        media_sink.set_available_type(MediaCodecType::MediaCodecAudioPCM);
-        media_sink.set_audio_type(audio_type);
+        media_sink.set_available_type(self.config.codec);
+        media_sink.set_audio_type(self.config.stream_type);
 
-        let mut audio_config = AudioConfiguration::new();
-        audio_config.sample_rate = Some (sample_rate);
-        audio_config.number_of_bits = Some(16);
-        audio_config.number_of_channels = Some(number_of_channels);
-
-        media_sink.audio_configs.push(audio_config);
+        for config in &self.config.configs {
+            media_sink.audio_configs.push(config.to_protobuf());
+        }
 
         service.media_sink_service = Some(media_sink).into();
-        
+
         service
     }
 
     fn handle_message(&mut self, message: Message) {
-        match message {
-            Message { is_control: false, msg_type: 32768, .. } => { // SetupRequest
+        match MediaMessageType::from_u16(message.msg_type) {
+            Some(MediaMessageType::SetupRequest) => {
                 self.handle_media_setup_request(message);
             }
-            Message { .. } => {
+            Some(MediaMessageType::StartRequest) => {
+                self.handle_media_start_request(message);
+            }
+            Some(MediaMessageType::StopRequest) => {
+                self.handle_media_stop_request(message);
+            }
+            Some(MediaMessageType::MediaData) => {
+                self.handle_media_data(message, true);
+            }
+            Some(MediaMessageType::CodecData) => {
+                self.handle_codec_data(message);
+            }
+            _ => {
                 println!("Unsupported AudioChannel: {} {} {} {} {}", message.channel, message.is_control, message.length, message.msg_type, hex::encode(&message.data));
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Sender};
+
+    struct ChannelSink(Sender<(Option<u64>, Vec<u8>)>);
+
+    impl AudioSink for ChannelSink {
+        fn on_data(&mut self, _info: &AudioStreamInfo, timestamp: Option<u64>, data: &[u8]) {
+            self.0.send((timestamp, data.to_vec())).unwrap();
+        }
+    }
+
+    fn media_data(data: Vec<u8>) -> Message {
+        Message {
+            channel: 4,
+            is_control: false,
+            length: data.len() as u16,
+            msg_type: MediaMessageType::MediaData as u16,
+            data,
+        }
+    }
+
+    #[test]
+    fn media_data_with_timestamp() {
+        let (sender, receiver) = channel();
+        let mut service = AudioService::new(AudioServiceConfig::media(), Arc::new(ConnectionContext::new()))
+            .with_sink(ChannelSink(sender));
+
+        service.handle_media_data(media_data(vec![0, 0, 0, 0, 0, 0, 0x01, 0x02, 0xAA, 0xBB]), true);
+        assert_eq!(receiver.try_recv(), Ok((Some(0x0102), vec![0xAA, 0xBB])));
+
+        service.handle_media_data(media_data(vec![0xAA, 0xBB]), false);
+        assert_eq!(receiver.try_recv(), Ok((None, vec![0xAA, 0xBB])));
+    }
+
+    #[test]
+    fn short_media_data_is_dropped() {
+        let (sender, receiver) = channel();
+        let mut service = AudioService::new(AudioServiceConfig::media(), Arc::new(ConnectionContext::new()))
+            .with_sink(ChannelSink(sender));
+
+        service.handle_media_data(media_data(vec![]), true);
+        service.handle_media_data(media_data(vec![0, 0, 0, 0, 0, 0, 0]), true);
+        assert!(receiver.try_recv().is_err());
+
+        // An empty payload after the timestamp is still passed on
+        service.handle_media_data(media_data(vec![0, 0, 0, 0, 0, 0, 0, 0x01]), true);
+        assert_eq!(receiver.try_recv(), Ok((Some(1), vec![])));
+    }
+
+    #[cfg(feature = "aac")]
+    #[test]
+    fn decoded_stream_info() {
+        // Advertised as 16 kHz mono, but the phone configures 48 kHz stereo
+        let config = AudioServiceConfig::new(AudioStreamType::Media, MediaCodecType::MediaCodecAudioAACLC, vec![AudioConfig::new(16000, 16, 1)]);
+        let (sender, receiver) = channel();
+        let mut service = AudioService::new(config.with_decoding(), Arc::new(ConnectionContext::new()))
+            .with_sink(ChannelSink(sender));
+
+        let mut start = media::Start::new();
+        start.set_session_id(1);
+        start.set_configuration_index(0);
+        service.handle_media_start_request(Message::new_with_protobuf_message(4, false, start, MediaMessageType::StartRequest as u16));
+
+        let mut codec_data = media_data(vec![0x11, 0x90]);
+        codec_data.msg_type = MediaMessageType::CodecData as u16;
+        service.handle_codec_data(codec_data);
+
+        let info = service.stream_info();
+        assert_eq!((info.codec, info.sample_rate, info.number_of_channels), (MediaCodecType::MediaCodecAudioPCM, 16000, 1));
+
+        // A silent stereo frame
+        service.handle_media_data(media_data(vec![0, 0, 0, 0, 0, 0, 0, 0, 0x21, 0x00, 0x49, 0x90, 0x02, 0x19, 0x00, 0x23, 0x80]), true);
+        let (_, pcm) = receiver.try_recv().unwrap();
+        assert_eq!(pcm.len(), 1024 * 2 * 2);
+
+        let info = service.stream_info();
+        assert_eq!((info.codec, info.sample_rate, info.number_of_bits, info.number_of_channels), (MediaCodecType::MediaCodecAudioPCM, 48000, 16, 2));
+    }
+}
@@ -2,22 +2,145 @@ use crate::connection::ConnectionContext;
 use crate::message::{ControlMessageType, Message};
 use crate::protobuf::control::audio_focus_notification::AudioFocusStateType;
 use crate::protobuf::control::audio_focus_request_notification::AudioFocusRequestType;
-use crate::protobuf::control::{AudioFocusNotification, AudioFocusRequestNotification, ServiceDiscoveryResponse};
-use crate::service::Service;
+use crate::protobuf::control::{AudioFocusNotification, AudioFocusRequestNotification, NavFocusNotification, NavFocusRequestNotification, NavFocusType, ServiceDiscoveryResponse};
+use crate::service::{EventSender, Service};
 use protobuf::{Enum, Message as ProtoMessage};
-use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+// Who is guiding: the head unit's built-in navigation or the phone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NavFocus {
+    Native,
+    Projected,
+}
+
+impl From<NavFocusType> for NavFocus {
+    fn from(value: NavFocusType) -> Self {
+        match value {
+            NavFocusType::NavFocus1 => NavFocus::Native,
+            NavFocusType::NavFocus2 => NavFocus::Projected,
+        }
+    }
+}
+
+impl From<NavFocus> for NavFocusType {
+    fn from(value: NavFocus) -> Self {
+        match value {
+            NavFocus::Native => NavFocusType::NavFocus1,
+            NavFocus::Projected => NavFocusType::NavFocus2,
+        }
+    }
+}
+
+// Decides which focus to grant, gets the requested and the current focus
+pub type NavFocusPolicy = Box<dyn Fn(NavFocus, NavFocus) -> NavFocus + Send>;
+
+struct NavFocusState {
+    focus: NavFocus,
+    event_sender: EventSender<NavFocus>,
+}
+
+impl NavFocusState {
+    fn set(&mut self, focus: NavFocus, context: &ConnectionContext) {
+        let mut notification = NavFocusNotification::new();
+        notification.set_focus_type(focus.into());
+
+        let mut commands = context.commands().lock().unwrap();
+        commands.send_message(Message::new_with_protobuf_message(
+            0,
+            false,
+            notification,
+            ControlMessageType::NavFocusNotification as u16
+        ), true);
+
+        if focus != self.focus {
+            println!("ControlService: Nav focus {:?} -> {:?}", self.focus, focus);
+
+            self.focus = focus;
+
+            self.event_sender.send(focus);
+        }
+    }
+}
+
+// Lets the head unit take navigation focus from the phone (e.g. when the built-in
+// navigation starts guiding) and hand it back
+#[derive(Clone)]
+pub struct NavFocusHandle {
+    state: Arc<Mutex<NavFocusState>>,
+    context: Arc<ConnectionContext>,
+}
+
+impl NavFocusHandle {
+    pub fn focus(&self) -> NavFocus {
+        self.state.lock().unwrap().focus
+    }
+
+    pub fn claim(&self) {
+        self.state.lock().unwrap().set(NavFocus::Native, &self.context);
+    }
+
+    pub fn release(&self) {
+        self.state.lock().unwrap().set(NavFocus::Projected, &self.context);
+    }
+}
 
 pub struct ControlService {
+    nav_focus: Arc<Mutex<NavFocusState>>,
+    nav_focus_policy: NavFocusPolicy,
     context: Arc<ConnectionContext>,
 }
 
 impl ControlService {
     pub fn new(context: Arc<ConnectionContext>) -> Self {
         Self {
+            nav_focus: Arc::new(Mutex::new(NavFocusState {
+                focus: NavFocus::Projected,
+                event_sender: EventSender::none(),
+            })),
+            // The phone gets focus unless the head unit claimed it
+            nav_focus_policy: Box::new(|requested, current| {
+                if current == NavFocus::Native { current } else { requested }
+            }),
             context,
         }
     }
 
+    pub fn with_nav_focus_policy<F: Fn(NavFocus, NavFocus) -> NavFocus + Send + 'static>(mut self, policy: F) -> Self {
+        self.nav_focus_policy = Box::new(policy);
+
+        self
+    }
+
+    // Receives every change of the navigation focus
+    pub fn with_nav_focus_sender(self, event_sender: Sender<NavFocus>) -> Self {
+        self.nav_focus.lock().unwrap().event_sender.set(event_sender);
+
+        self
+    }
+
+    pub fn nav_focus_handle(&self) -> NavFocusHandle {
+        NavFocusHandle {
+            state: Arc::clone(&self.nav_focus),
+            context: Arc::clone(&self.context),
+        }
+    }
+
+    fn handle_nav_focus_request_notification(&mut self, message: Message) {
+        let data = NavFocusRequestNotification::parse_from_bytes(message.data.as_slice()).unwrap();
+        let requested = data.focus_type.and_then(|focus_type| focus_type.enum_value().ok())
+            .map(NavFocus::from)
+            .unwrap_or(NavFocus::Projected);
+
+        let mut state = self.nav_focus.lock().unwrap();
+        let granted = (self.nav_focus_policy)(requested, state.focus);
+
+        println!("Nav Focus Request: {:?}, granted {:?}", requested, granted);
+
+        state.set(granted, &self.context);
+    }
+
     fn handle_audio_focus_request_notification(&mut self, message: Message) {
         let data  = AudioFocusRequestNotification::parse_from_bytes(message.data.as_slice()).unwrap();
         println!("{:#?} {}", data, data.request.unwrap().unwrap().value());
@@ -88,6 +211,9 @@ impl Service for ControlService {
                 ControlMessageType::AudioFocusRequestNotification => {
                     self.handle_audio_focus_request_notification(message);
                 }
+                ControlMessageType::NavFocusRequestNotification => {
+                    self.handle_nav_focus_request_notification(message);
+                }
                 _ => {
                     println!("Unsupported Control: {} {} {} {} {}", message.channel, message.is_control, message.length, message.msg_type, hex::encode(&message.data));
                 }
@@ -1,29 +1,456 @@
 use crate::connection::ConnectionContext;
 use crate::message::{InputMessageType, Message};
 use crate::protobuf::common::MessageStatus;
+use crate::protobuf::control::service::input_source_service::TouchConfig;
 use crate::protobuf::control::service::InputSourceService;
 use crate::protobuf::input;
-use crate::protobuf::input::{KeyBindingRequest, KeyCode};
-use crate::service::Service;
-use protobuf::Message as ProtoMessage;
-use std::sync::Arc;
+use crate::protobuf::input::KeyBindingRequest;
+use crate::service::video::VideoServiceConfig;
+use crate::service::{EventSender, Service};
+use protobuf::{Enum, Message as ProtoMessage};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub use crate::protobuf::input::touch_event::PointerAction;
+pub use crate::protobuf::input::KeyCode;
+
+// Android KeyEvent meta state flags
+pub const META_SHIFT_ON: u32 = 0x01;
+pub const META_ALT_ON: u32 = 0x02;
+pub const META_SYM_ON: u32 = 0x04;
+pub const META_CTRL_ON: u32 = 0x1000;
+pub const META_META_ON: u32 = 0x10000;
+pub const META_CAPS_LOCK_ON: u32 = 0x100000;
+pub const META_NUM_LOCK_ON: u32 = 0x200000;
+
+const DEFAULT_LONG_PRESS_DURATION: Duration = Duration::from_millis(500);
+
+// Maps the coordinates of the physical touch panel onto the video the phone renders,
+// the part of the video hidden by the margins is not reachable by touch.
+#[derive(Clone, Copy, Debug)]
+pub struct TouchscreenConfig {
+    pub panel_width: u32,
+    pub panel_height: u32,
+    pub video_width: u32,
+    pub video_height: u32,
+    pub margin_width: u32,
+    pub margin_height: u32,
+}
+
+impl TouchscreenConfig {
+    pub fn new(panel_width: u32, panel_height: u32, video: &VideoServiceConfig) -> Self {
+        let (video_width, video_height) = video.video_size();
+
+        Self {
+            panel_width,
+            panel_height,
+            video_width,
+            video_height,
+            margin_width: video.margin_width,
+            margin_height: video.margin_height,
+        }
+    }
+
+    pub fn scale(&self, x: u32, y: u32) -> (u32, u32) {
+        (
+            Self::scale_axis(x, self.panel_width, self.video_width, self.margin_width),
+            Self::scale_axis(y, self.panel_height, self.video_height, self.margin_height),
+        )
+    }
+
+    fn scale_axis(value: u32, panel: u32, video: u32, margin: u32) -> u32 {
+        let visible = video.saturating_sub(margin) as u64;
+        let value = value.min(panel.saturating_sub(1)) as u64;
+
+        (margin / 2) + (value * visible / panel.max(1) as u64) as u32
+    }
+}
+
+// A touchpad reports in its own coordinate space, the phone maps it onto the UI itself
+#[derive(Clone, Copy, Debug)]
+pub struct TouchpadConfig {
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct InputServiceConfig {
+    pub touchscreen: Option<TouchscreenConfig>,
+    pub touchpad: Option<TouchpadConfig>,
+    // Keys the head unit can deliver, the phone only binds a subset of them
+    pub keycodes: Vec<KeyCode>,
+    // Keys held at least this long are released as long press
+    pub long_press_duration: Duration,
+}
+
+impl InputServiceConfig {
+    pub fn with_keycodes(mut self, keycodes: &[KeyCode]) -> Self {
+        for keycode in keycodes {
+            if !self.keycodes.contains(keycode) {
+                self.keycodes.push(*keycode);
+            }
+        }
+
+        self
+    }
+}
+
+impl Default for InputServiceConfig {
+    fn default() -> Self {
+        Self {
+            touchscreen: None,
+            touchpad: None,
+            long_press_duration: DEFAULT_LONG_PRESS_DURATION,
+            keycodes: vec![
+                KeyCode::KeycodeDPadUp,
+                KeyCode::KeycodeDPadDown,
+                KeyCode::KeycodeDPadLeft,
+                KeyCode::KeycodeDPadRight,
+                KeyCode::KeycodeRotaryController,
+                KeyCode::KeycodeDPadCenter,
+                KeyCode::KeycodeHome,
+                KeyCode::KeycodeBack,
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    // The phone bound these keys, only they should be sent from now on
+    KeysBound(Vec<KeyCode>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TouchSurface {
+    Touchscreen,
+    Touchpad,
+}
+
+// A touch point in panel coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TouchPointer {
+    pub id: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Default)]
+struct KeyState {
+    // keycode -> time it went down
+    pressed: BTreeMap<i32, Instant>,
+}
+
+impl KeyState {
+    // Modifiers derived from the modifier keys currently held
+    fn metastate(&self) -> u32 {
+        self.pressed.keys().fold(0, |metastate, keycode| {
+            metastate | match KeyCode::from_i32(*keycode) {
+                Some(KeyCode::KeycodeShiftLeft | KeyCode::KeycodeShiftRight) => META_SHIFT_ON,
+                Some(KeyCode::KeycodeAltLeft | KeyCode::KeycodeAltRight) => META_ALT_ON,
+                Some(KeyCode::KeycodeCtrlLeft | KeyCode::KeycodeCtrlRight) => META_CTRL_ON,
+                Some(KeyCode::KeycodeMetaLeft | KeyCode::KeycodeMetaRight) => META_META_ON,
+                Some(KeyCode::KeycodeSYM) => META_SYM_ON,
+                _ => 0,
+            }
+        })
+    }
+}
+
+// Sends input to the phone on the channel the InputService was registered on
+#[derive(Clone)]
+pub struct InputHandle {
+    channel_id: Arc<AtomicU8>,
+    // Channel of the VideoService of the targeted display, None for the main display
+    disp_channel_id: Option<u8>,
+    touchscreen: Option<TouchscreenConfig>,
+    touchpad: Option<TouchpadConfig>,
+    long_press_duration: Duration,
+    key_state: Arc<Mutex<KeyState>>,
+    started: Instant,
+    context: Arc<ConnectionContext>,
+}
+
+impl InputHandle {
+    // Input for a specific display, `disp_channel_id` is the channel of its VideoService
+    // (see `ConnectionContext::display_channel_id`)
+    pub fn for_display(&self, disp_channel_id: u8) -> Self {
+        let mut handle = self.clone();
+        handle.disp_channel_id = Some(disp_channel_id);
+
+        handle
+    }
+
+    // Additional modifiers in `metastate` are combined with the ones of held modifier keys
+    pub fn key_down(&self, keycode: KeyCode, metastate: u32) {
+        let metastate = {
+            let mut key_state = self.key_state.lock().unwrap();
+            key_state.pressed.entry(keycode as i32).or_insert_with(Instant::now);
+
+            metastate | key_state.metastate()
+        };
+
+        self.send_key_event(keycode, true, metastate, false);
+    }
+
+    pub fn key_up(&self, keycode: KeyCode, metastate: u32) {
+        let (metastate, long_press) = {
+            let mut key_state = self.key_state.lock().unwrap();
+            let pressed = key_state.pressed.remove(&(keycode as i32));
+
+            let long_press = pressed.is_some_and(|pressed| pressed.elapsed() >= self.long_press_duration);
+
+            (metastate | key_state.metastate(), long_press)
+        };
+
+        self.send_key_event(keycode, false, metastate, long_press);
+    }
+
+    pub fn key_press(&self, keycode: KeyCode, metastate: u32) {
+        self.key_down(keycode, metastate);
+        self.key_up(keycode, metastate);
+    }
+
+    pub fn send_key_event(&self, keycode: KeyCode, down: bool, metastate: u32, long_press: bool) {
+        let mut key = input::Key::new();
+        key.keycode = Some(keycode as u32);
+        key.down = Some(down);
+        key.metastate = Some(metastate);
+        key.long_press = Some(long_press);
+
+        let mut key_event = input::KeyEvent::new();
+        key_event.keys.push(key);
+
+        let mut report = input::InputReport::new();
+        report.key_event = Some(key_event).into();
+
+        self.send_input_report(report);
+    }
+
+    pub fn send_rotary_event(&self, delta: i32) {
+        self.send_relative_event(KeyCode::KeycodeRotaryController, delta);
+    }
+
+    pub fn send_relative_event(&self, keycode: KeyCode, delta: i32) {
+        let mut rel = input::RelativeEvent_Rel::new();
+        rel.keycode = Some(keycode as u32);
+        rel.delta = Some(delta);
+
+        let mut relative_event = input::RelativeEvent::new();
+        relative_event.data.push(rel);
+
+        let mut report = input::InputReport::new();
+        report.relative_event = Some(relative_event).into();
+
+        self.send_input_report(report);
+    }
+
+    // Absolute position of an axis like control, e.g. a slider or a joystick
+    pub fn send_absolute_event(&self, keycode: KeyCode, value: i32) {
+        let mut abs = input::AbsoluteEvent_Abs::new();
+        abs.keycode = Some(keycode as u32);
+        abs.value = Some(value);
+
+        let mut absolute_event = input::AbsoluteEvent::new();
+        absolute_event.data.push(abs);
+
+        let mut report = input::InputReport::new();
+        report.absolute_event = Some(absolute_event).into();
+
+        self.send_input_report(report);
+    }
+
+    pub fn send_touch_event(&self, action: PointerAction, action_index: u32, pointers: &[TouchPointer]) {
+        let Some(touchscreen) = self.touchscreen else {
+            println!("InputService: No touchscreen configured");
+            return;
+        };
+
+        let touch_event = Self::touch_event(action, action_index, pointers, |x, y| touchscreen.scale(x, y));
+
+        let mut report = input::InputReport::new();
+        report.touch_event = Some(touch_event).into();
+
+        self.send_input_report(report);
+    }
+
+    pub fn send_touchpad_event(&self, action: PointerAction, action_index: u32, pointers: &[TouchPointer]) {
+        let Some(touchpad) = self.touchpad else {
+            println!("InputService: No touchpad configured");
+            return;
+        };
+
+        let touch_event = Self::touch_event(action, action_index, pointers, |x, y| {
+            (x.min(touchpad.width.saturating_sub(1)), y.min(touchpad.height.saturating_sub(1)))
+        });
+
+        let mut report = input::InputReport::new();
+        report.touchpad_event = Some(touch_event).into();
+
+        self.send_input_report(report);
+    }
+
+    // Keys held at least this long are released as long press, see `InputServiceConfig::long_press_duration`
+    pub fn long_press_duration(&self) -> Duration {
+        self.long_press_duration
+    }
+
+    // Coordinate space the pointers of a surface are expected in, None if it isn't configured
+    pub fn touch_size(&self, surface: TouchSurface) -> Option<(u32, u32)> {
+        match surface {
+            TouchSurface::Touchscreen => self.touchscreen.map(|touchscreen| (touchscreen.panel_width, touchscreen.panel_height)),
+            TouchSurface::Touchpad => self.touchpad.map(|touchpad| (touchpad.width, touchpad.height)),
+        }
+    }
+
+    pub fn send_surface_event(&self, surface: TouchSurface, action: PointerAction, action_index: u32, pointers: &[TouchPointer]) {
+        match surface {
+            TouchSurface::Touchscreen => self.send_touch_event(action, action_index, pointers),
+            TouchSurface::Touchpad => self.send_touchpad_event(action, action_index, pointers),
+        }
+    }
+
+    pub fn touch_down(&self, id: u32, x: u32, y: u32) {
+        self.send_touch_event(PointerAction::TouchActionDown, 0, &[TouchPointer { id, x, y }]);
+    }
+
+    pub fn touch_move(&self, id: u32, x: u32, y: u32) {
+        self.send_touch_event(PointerAction::TouchActionMove, 0, &[TouchPointer { id, x, y }]);
+    }
+
+    pub fn touch_up(&self, id: u32, x: u32, y: u32) {
+        self.send_touch_event(PointerAction::TouchActionUp, 0, &[TouchPointer { id, x, y }]);
+    }
+
+    pub fn touch_cancel(&self, id: u32, x: u32, y: u32) {
+        self.send_touch_event(PointerAction::TouchActionCancel, 0, &[TouchPointer { id, x, y }]);
+    }
+
+    pub fn touchpad_down(&self, id: u32, x: u32, y: u32) {
+        self.send_touchpad_event(PointerAction::TouchActionDown, 0, &[TouchPointer { id, x, y }]);
+    }
+
+    pub fn touchpad_move(&self, id: u32, x: u32, y: u32) {
+        self.send_touchpad_event(PointerAction::TouchActionMove, 0, &[TouchPointer { id, x, y }]);
+    }
+
+    pub fn touchpad_up(&self, id: u32, x: u32, y: u32) {
+        self.send_touchpad_event(PointerAction::TouchActionUp, 0, &[TouchPointer { id, x, y }]);
+    }
+
+    pub fn touchpad_cancel(&self, id: u32, x: u32, y: u32) {
+        self.send_touchpad_event(PointerAction::TouchActionCancel, 0, &[TouchPointer { id, x, y }]);
+    }
+
+    fn touch_event<F: Fn(u32, u32) -> (u32, u32)>(action: PointerAction, action_index: u32, pointers: &[TouchPointer], map: F) -> input::TouchEvent {
+        let mut touch_event = input::TouchEvent::new();
+        touch_event.set_action(action);
+        touch_event.action_index = Some(action_index);
+
+        for pointer in pointers {
+            let (x, y) = map(pointer.x, pointer.y);
+
+            let mut pointer_data = input::touch_event::Pointer::new();
+            pointer_data.x = Some(x);
+            pointer_data.y = Some(y);
+            pointer_data.pointer_id = Some(pointer.id);
+
+            touch_event.pointer_data.push(pointer_data);
+        }
+
+        touch_event
+    }
+
+    fn send_input_report(&self, mut report: input::InputReport) {
+        let channel_id = self.channel_id.load(Ordering::Relaxed);
+        if channel_id == 0 {
+            println!("InputService: Not registered yet, dropping input");
+            return;
+        }
+
+        // Monotonic, the wall clock may jump when the head unit syncs its time
+        report.set_timestamp(self.started.elapsed().as_nanos() as u64);
+        report.disp_channel_id = self.disp_channel_id.map(|channel| channel as i32);
+
+        let mut commands = self.context.commands().lock().unwrap();
+        commands.send_message(Message::new_with_protobuf_message(
+            channel_id,
+            false,
+            report,
+            InputMessageType::InputReport as u16
+        ), true);
+    }
+}
 
 pub struct InputService {
+    config: InputServiceConfig,
+    channel_id: Arc<AtomicU8>,
+    event_sender: EventSender<InputEvent>,
+    key_state: Arc<Mutex<KeyState>>,
+    started: Instant,
     context: Arc<ConnectionContext>,
 }
 
 impl InputService {
-    pub fn new(context: Arc<ConnectionContext>) -> Self {
+    pub fn new(config: InputServiceConfig, context: Arc<ConnectionContext>) -> Self {
         Self {
+            config,
+            channel_id: Arc::new(AtomicU8::new(0)),
+            event_sender: EventSender::none(),
+            key_state: Arc::new(Mutex::new(KeyState::default())),
+            started: Instant::now(),
             context,
         }
     }
 
+    pub fn with_event_sender(mut self, event_sender: Sender<InputEvent>) -> Self {
+        self.event_sender.set(event_sender);
+
+        self
+    }
+
+    pub fn handle(&self) -> InputHandle {
+        InputHandle {
+            channel_id: Arc::clone(&self.channel_id),
+            disp_channel_id: None,
+            touchscreen: self.config.touchscreen,
+            touchpad: self.config.touchpad,
+            long_press_duration: self.config.long_press_duration,
+            key_state: Arc::clone(&self.key_state),
+            started: self.started,
+            context: Arc::clone(&self.context),
+        }
+    }
+
     fn handle_binding_request(&mut self, message: Message) {
         let data = KeyBindingRequest::parse_from_bytes(message.data.as_slice()).unwrap();
 
+        let mut bound = vec![];
+        let mut unsupported = vec![];
+
+        for keycode in data.keycodes {
+            match KeyCode::from_i32(keycode) {
+                Some(keycode) if self.config.keycodes.contains(&keycode) => bound.push(keycode),
+                _ => unsupported.push(keycode),
+            }
+        }
+
+        let status = if unsupported.is_empty() {
+            println!("InputService: Keys bound: {:?}", bound);
+
+            self.event_sender.send(InputEvent::KeysBound(bound));
+
+            MessageStatus::Ok
+        } else {
+            println!("InputService: Binding request for unsupported keycodes {:?}", unsupported);
+
+            MessageStatus::Error
+        };
+
         let mut config = input::BindingResponse::new();
-        config.set_status(MessageStatus::Ok);
+        config.set_status(status);
 
         let mut commands = self.context.commands().lock().unwrap();
         commands.send_message(Message::new_with_protobuf_message(
@@ -37,37 +464,118 @@ impl InputService {
 
 impl Service for InputService {
     fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service {
+        self.channel_id.store(channel_id, Ordering::Relaxed);
+
         let mut service = crate::protobuf::control::Service::new();
         service.id = Some(channel_id as u32);
 
-        /*let mut touch_config = TouchConfig::new();
-        touch_config.width = Some(800);
-        touch_config.height = Some(400);*/
-
         let mut input_source = InputSourceService::new();
-        //input_source.touchscreen = Some(touch_config).into();
-        input_source.keycodes_supported.push(KeyCode::KeycodeDPadUp as u32);
-        input_source.keycodes_supported.push(KeyCode::KeycodeDPadDown as u32);
-        input_source.keycodes_supported.push(KeyCode::KeycodeDPadLeft as u32);
-        input_source.keycodes_supported.push(KeyCode::KeycodeDPadRight as u32);
-        input_source.keycodes_supported.push(KeyCode::KeycodeRotaryController as u32);
-        input_source.keycodes_supported.push(KeyCode::KeycodeDPadCenter as u32);
-        input_source.keycodes_supported.push(KeyCode::KeycodeHome as u32);
-        input_source.keycodes_supported.push(KeyCode::KeycodeBack as u32);
+
+        if let Some(touchscreen) = self.config.touchscreen {
+            let mut touch_config = TouchConfig::new();
+            touch_config.width = Some(touchscreen.video_width);
+            touch_config.height = Some(touchscreen.video_height);
+
+            input_source.touchscreen = Some(touch_config).into();
+        }
+
+        if let Some(touchpad) = self.config.touchpad {
+            let mut touch_config = TouchConfig::new();
+            touch_config.width = Some(touchpad.width);
+            touch_config.height = Some(touchpad.height);
+
+            input_source.touchpad = Some(touch_config).into();
+        }
+
+        for keycode in &self.config.keycodes {
+            input_source.keycodes_supported.push(*keycode as u32);
+        }
 
         service.input_source_service = Some(input_source).into();
-        
+
         service
     }
 
     fn handle_message(&mut self, message: Message) {
-        match message {
-            Message { is_control: false, msg_type: 32770, .. } => { // BindingRequest
+        match InputMessageType::from_u16(message.msg_type) {
+            Some(InputMessageType::BindingRequest) if !message.is_control => {
                 self.handle_binding_request(message);
             }
-            Message { .. } => {
+            _ => {
                 println!("Unsupported InputChannel: {} {} {} {} {}", message.channel, message.is_control, message.length, message.msg_type, hex::encode(&message.data));
             }
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    // An InputReport as the phone would see it
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) enum Sent {
+        Key { keycode: KeyCode, down: bool, long_press: bool },
+        Relative(KeyCode, i32),
+        // Action, action index and (id, x, y) of the pointers
+        Touch(PointerAction, u32, Vec<(u32, u32, u32)>),
+        Touchpad(PointerAction, u32, Vec<(u32, u32, u32)>),
+    }
+
+    // A registered InputService with a 100x100 touchpad
+    pub(crate) fn input_service(keycodes: &[KeyCode]) -> (InputService, Arc<ConnectionContext>) {
+        let context = Arc::new(ConnectionContext::new());
+
+        let config = InputServiceConfig {
+            touchpad: Some(TouchpadConfig { width: 100, height: 100 }),
+            ..Default::default()
+        }.with_keycodes(keycodes);
+
+        let service = InputService::new(config, Arc::clone(&context));
+        service.protobuf_descriptor(1);
+
+        (service, context)
+    }
+
+    pub(crate) fn input_handle() -> (InputHandle, Arc<ConnectionContext>) {
+        let (service, context) = input_service(&[]);
+
+        (service.handle(), context)
+    }
+
+    // Takes the reports queued since the last call
+    pub(crate) fn sent(context: &ConnectionContext) -> Vec<Sent> {
+        let pointers = |event: &input::TouchEvent| -> Vec<(u32, u32, u32)> {
+            event.pointer_data.iter().map(|pointer| (pointer.pointer_id(), pointer.x(), pointer.y())).collect()
+        };
+
+        let mut sent = vec![];
+
+        for (message, _) in context.commands().lock().unwrap().messages_to_send() {
+            if message.msg_type != InputMessageType::InputReport as u16 {
+                continue;
+            }
+
+            let report: input::InputReport = message.to_protobuf_message();
+
+            for key in report.key_event.keys.iter() {
+                sent.push(Sent::Key {
+                    keycode: KeyCode::from_i32(key.keycode() as i32).unwrap(),
+                    down: key.down(),
+                    long_press: key.long_press(),
+                });
+            }
+            for rel in report.relative_event.data.iter() {
+                sent.push(Sent::Relative(KeyCode::from_i32(rel.keycode() as i32).unwrap(), rel.delta()));
+            }
+            if let Some(event) = report.touch_event.as_ref() {
+                sent.push(Sent::Touch(event.action(), event.action_index(), pointers(event)));
+            }
+            if let Some(event) = report.touchpad_event.as_ref() {
+                sent.push(Sent::Touchpad(event.action(), event.action_index(), pointers(event)));
+            }
+        }
+
+        sent
+    }
+}
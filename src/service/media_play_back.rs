@@ -1,36 +1,174 @@
 use crate::connection::ConnectionContext;
-use crate::message::Message;
+use crate::media::album_art::{AlbumArt, AlbumArtCache, AlbumArtConfig};
+use crate::message::{MediaPlaybackMessageType, Message};
 use crate::protobuf::control::service::MediaPlaybackStatusService;
 use crate::protobuf::playback;
-use crate::service::Service;
+use crate::service::{EventSender, Service};
 use protobuf::Message as ProtoMessage;
-use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub use crate::protobuf::playback::media_playback_status::State as PlaybackState;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlaybackStatus {
+    pub state: Option<PlaybackState>,
+    // Package of the app that is playing
+    pub source: Option<String>,
+    pub position: Option<Duration>,
+    pub shuffle: Option<bool>,
+    pub repeat: Option<bool>,
+    pub repeat_one: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaMetadata {
+    pub song: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    // Encoded image as sent by the phone, usually PNG or JPEG
+    pub album_art: Option<Vec<u8>>,
+    pub playlist: Option<String>,
+    pub duration: Option<Duration>,
+    pub rating: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NowPlaying {
+    pub status: PlaybackStatus,
+    pub metadata: MediaMetadata,
+    // `metadata.album_art` as processed by the album art cache
+    pub album_art: Option<Arc<AlbumArt>>,
+}
+
+impl NowPlaying {
+    pub fn is_playing(&self) -> bool {
+        self.status.state == Some(PlaybackState::MediaServiceStatePlaying)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaPlaybackEvent {
+    // Sent by the phone about once a second while playing
+    Status(PlaybackStatus),
+    // Sent when the track changes
+    Metadata(MediaMetadata),
+    // Sent only when the cover actually changes, not for every metadata update
+    AlbumArt(Option<Arc<AlbumArt>>),
+}
+
+// Queries what the phone is playing from anywhere in the application
+#[derive(Clone)]
+pub struct MediaPlaybackHandle {
+    now_playing: Arc<Mutex<NowPlaying>>,
+}
+
+impl MediaPlaybackHandle {
+    pub fn now_playing(&self) -> NowPlaying {
+        self.now_playing.lock().unwrap().clone()
+    }
+
+    pub fn album_art(&self) -> Option<Arc<AlbumArt>> {
+        self.now_playing.lock().unwrap().album_art.clone()
+    }
+}
 
 pub struct MediaPlayBackService {
+    now_playing: Arc<Mutex<NowPlaying>>,
+    album_art_cache: AlbumArtCache,
+    event_sender: EventSender<MediaPlaybackEvent>,
     context: Arc<ConnectionContext>,
 }
 
 impl MediaPlayBackService {
     pub fn new(context: Arc<ConnectionContext>) -> Self {
         Self {
+            now_playing: Arc::new(Mutex::new(NowPlaying::default())),
+            album_art_cache: AlbumArtCache::new(AlbumArtConfig::default()),
+            event_sender: EventSender::none(),
             context,
         }
     }
 
-    pub fn handle_media_start_request(&mut self, message: Message) {
+    pub fn with_album_art_config(mut self, config: AlbumArtConfig) -> Self {
+        self.album_art_cache = AlbumArtCache::new(config);
+
+        self
+    }
+
+    pub fn with_event_sender(mut self, event_sender: Sender<MediaPlaybackEvent>) -> Self {
+        self.event_sender.set(event_sender);
+
+        self
+    }
+
+    pub fn handle(&self) -> MediaPlaybackHandle {
+        MediaPlaybackHandle {
+            now_playing: Arc::clone(&self.now_playing),
+        }
+    }
+
+    fn send_event(&mut self, event: MediaPlaybackEvent) {
+        self.event_sender.send(event);
+    }
+
+    pub fn handle_playback_status(&mut self, message: Message) {
         let req = playback::MediaPlaybackStatus::parse_from_bytes(message.data.as_slice()).unwrap();
 
-        /*let mut data = data.lock().unwrap();
-        data.session_id = req.session_id;*/
+        let status = PlaybackStatus {
+            state: req.state.and_then(|state| state.enum_value().ok()),
+            source: req.source,
+            position: req.seconds.map(|seconds| Duration::from_secs(seconds as u64)),
+            shuffle: req.shuffle,
+            repeat: req.repeat,
+            repeat_one: req.repeat_one,
+        };
+
+        self.now_playing.lock().unwrap().status = status.clone();
 
-        //println!("MediaStartRequest MEDIA_PLAYBACK_STATUS: {:?}", req)
+        self.send_event(MediaPlaybackEvent::Status(status));
+    }
+
+    pub fn handle_playback_metadata(&mut self, message: Message) {
+        let req = playback::MediaMetaData::parse_from_bytes(message.data.as_slice()).unwrap();
+
+        let metadata = MediaMetadata {
+            song: req.song,
+            artist: req.artist,
+            album: req.album,
+            album_art: req.album_art.filter(|album_art| !album_art.is_empty()),
+            playlist: req.playlist,
+            duration: req.duration.map(|seconds| Duration::from_secs(seconds as u64)),
+            rating: req.rating,
+        };
+
+        println!("MediaPlayBackService: Now playing {:?} by {:?}", metadata.song, metadata.artist);
+
+        let album_art = metadata.album_art.as_deref().map(|album_art| self.album_art_cache.insert(album_art));
+
+        let album_art_changed = {
+            let mut now_playing = self.now_playing.lock().unwrap();
+            now_playing.metadata = metadata.clone();
+
+            let changed = now_playing.album_art.as_ref().map(|art| art.hash) != album_art.as_ref().map(|art| art.hash);
+            now_playing.album_art = album_art.clone();
+
+            changed
+        };
+
+        self.send_event(MediaPlaybackEvent::Metadata(metadata));
+
+        if album_art_changed {
+            self.send_event(MediaPlaybackEvent::AlbumArt(album_art));
+        }
     }
 }
 
 impl Service for MediaPlayBackService {
     fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service {
         let mut service = crate::protobuf::control::Service::new();
-        service.id = Some(8);
+        service.id = Some(channel_id as u32);
 
         service.media_playback_service = Some(MediaPlaybackStatusService::new()).into();
 
@@ -38,16 +176,14 @@ impl Service for MediaPlayBackService {
     }
 
     fn handle_message(&mut self, message: Message) {
-        match message {
-            Message { msg_type: 32769, .. } => { // MEDIA_PLAYBACK_STATUS
-                self.handle_media_start_request(message);
+        match MediaPlaybackMessageType::from_u16(message.msg_type) {
+            Some(MediaPlaybackMessageType::PlaybackStatus) if !message.is_control => {
+                self.handle_playback_status(message);
             }
-            Message { msg_type: 32771, .. } => { // MEDIA_PLAYBACK_METADATA
-                let req = playback::MediaMetaData::parse_from_bytes(message.data.as_slice()).unwrap();
-
-                //println!("MediaStartRequest MEDIA_PLAYBACK_METADATA: {:?}", req)
+            Some(MediaPlaybackMessageType::PlaybackMetadata) if !message.is_control => {
+                self.handle_playback_metadata(message);
             }
-            Message { .. } => {
+            _ => {
                 println!("Unsupported MediaPlayBackChannel: {} {} {} {} {}", message.channel, message.is_control, message.length, message.msg_type, hex::encode(&message.data));
             }
         }
@@ -1,20 +1,241 @@
 use crate::connection::ConnectionContext;
-use crate::message::Message;
+use crate::message::{MediaMessageType, Message};
+use crate::protobuf::common::MessageStatus;
 use crate::protobuf::control::service::MediaSourceService;
-use crate::protobuf::media::{AudioConfiguration, MediaCodecType};
+use crate::protobuf::media;
+use crate::protobuf::media::{MediaCodecType, MicrophoneRequest, MicrophoneResponse};
+use crate::service::audio::AudioConfig;
 use crate::service::Service;
-use std::sync::Arc;
+use protobuf::Message as ProtoMessage;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Size of a single media data frame sent to the phone
+const FRAME_DURATION_MS: u32 = 20;
+// How long stopping waits for a blocked `AudioSource::read` before the capture thread is left behind
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug)]
+pub struct MicrophoneOptions {
+    pub noise_suppression: bool,
+    pub echo_cancellation: bool,
+}
+
+// Set when the phone closes the microphone or the service is dropped
+#[derive(Clone, Debug, Default)]
+pub struct StopSignal(Arc<AtomicBool>);
+
+impl StopSignal {
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// Provides the head unit microphone audio as interleaved little endian PCM in the
+// format of the `AudioConfig` the MicrophoneService was created with.
+pub trait AudioSource: Send {
+    // `stop` can be kept to return from a blocking `read` early
+    fn open(&mut self, _config: &AudioConfig, _options: &MicrophoneOptions, _stop: &StopSignal) {}
+
+    // Should block until `buf` is filled or a short timeout passed, returns the number of bytes read
+    fn read(&mut self, buf: &mut [u8]) -> usize;
+
+    fn close(&mut self) {}
+}
+
+// Per capture, so a capture thread left behind can't pick up the next session
+struct CaptureState {
+    running: bool,
+    finished: bool,
+    unacked: i32,
+    max_unacked: i32,
+}
+
+impl CaptureState {
+    fn new(max_unacked: i32) -> Arc<(Mutex<CaptureState>, Condvar)> {
+        Arc::new((Mutex::new(CaptureState { running: true, finished: false, unacked: 0, max_unacked }), Condvar::new()))
+    }
+}
 
 pub struct MicrophoneService {
+    config: AudioConfig,
+    source: Option<Arc<Mutex<Box<dyn AudioSource>>>>,
+    session_id: i32,
+    state: Arc<(Mutex<CaptureState>, Condvar)>,
+    stop: StopSignal,
+    capture_thread: Option<JoinHandle<()>>,
+    started: Instant,
     context: Arc<ConnectionContext>,
 }
 
 impl MicrophoneService {
-    pub fn new(context: Arc<ConnectionContext>) -> Self {
+    pub fn new(config: AudioConfig, context: Arc<ConnectionContext>) -> Self {
         Self {
+            config,
+            source: None,
+            session_id: 0,
+            state: CaptureState::new(1),
+            stop: StopSignal::default(),
+            capture_thread: None,
+            started: Instant::now(),
             context,
         }
     }
+
+    pub fn with_source<A: AudioSource + 'static>(mut self, source: A) -> Self {
+        self.source = Some(Arc::new(Mutex::new(Box::new(source))));
+
+        self
+    }
+
+    fn handle_microphone_request(&mut self, message: Message) {
+        let data = MicrophoneRequest::parse_from_bytes(message.data.as_slice()).unwrap();
+
+        println!("MicrophoneRequest: open {} anc {} ec {} max_unacked {}", data.open(), data.anc_enabled(), data.ec_enabled(), data.max_unacked());
+
+        let status = if data.open() {
+            let options = MicrophoneOptions {
+                noise_suppression: data.anc_enabled(),
+                echo_cancellation: data.ec_enabled(),
+            };
+
+            self.start_capture(message.channel, options, data.max_unacked.unwrap_or(1).max(1))
+        } else {
+            self.stop_capture();
+
+            MessageStatus::Ok
+        };
+
+        let mut response = MicrophoneResponse::new();
+        response.set_status(status as i32);
+        response.set_session_id(self.session_id as u32);
+
+        let mut commands = self.context.commands().lock().unwrap();
+        commands.send_message(Message::new_with_protobuf_message(
+            message.channel,
+            false,
+            response,
+            MediaMessageType::MicResponse as u16
+        ), true);
+    }
+
+    fn handle_media_ack(&mut self, message: Message) {
+        let data = media::Ack::parse_from_bytes(message.data.as_slice()).unwrap();
+
+        if data.session_id() != self.session_id {
+            return;
+        }
+
+        let (state, condvar) = &*self.state;
+        let mut state = state.lock().unwrap();
+        state.unacked = (state.unacked - data.ack() as i32).max(0);
+        condvar.notify_all();
+    }
+
+    fn start_capture(&mut self, channel: u8, options: MicrophoneOptions, max_unacked: i32) -> MessageStatus {
+        self.stop_capture();
+
+        let Some(source) = self.source.as_ref().map(Arc::clone) else {
+            println!("MicrophoneService: No AudioSource");
+            return MessageStatus::Error;
+        };
+
+        self.session_id += 1;
+        self.state = CaptureState::new(max_unacked);
+        self.stop = StopSignal::default();
+
+        let config = self.config;
+        let state = Arc::clone(&self.state);
+        let stop = self.stop.clone();
+        let context = Arc::clone(&self.context);
+        let started = self.started;
+
+        self.capture_thread = Some(std::thread::spawn(move || {
+            let mut source = source.lock().unwrap();
+            source.open(&config, &options, &stop);
+
+            let frame_size = (config.sample_rate * FRAME_DURATION_MS / 1000 * config.number_of_channels * config.number_of_bits / 8) as usize;
+            let mut buf = vec![0u8; frame_size];
+
+            loop {
+                {
+                    let (state, condvar) = &*state;
+                    let mut state = state.lock().unwrap();
+
+                    while state.running && state.unacked >= state.max_unacked {
+                        state = condvar.wait_timeout(state, Duration::from_millis(100)).unwrap().0;
+                    }
+
+                    if !state.running {
+                        break;
+                    }
+                }
+
+                let read = source.read(&mut buf);
+                if read == 0 || stop.is_stopped() {
+                    continue;
+                }
+
+                let timestamp = started.elapsed().as_micros() as u64;
+
+                let mut data = Vec::with_capacity(read + 8);
+                data.extend_from_slice(&timestamp.to_be_bytes());
+                data.extend_from_slice(&buf[..read]);
+
+                state.0.lock().unwrap().unacked += 1;
+
+                let mut commands = context.commands().lock().unwrap();
+                commands.send_message(Message {
+                    channel,
+                    is_control: false,
+                    length: 0,
+                    msg_type: MediaMessageType::MediaData as u16,
+                    data,
+                }, true);
+            }
+
+            source.close();
+
+            let (state, condvar) = &*state;
+            state.lock().unwrap().finished = true;
+            condvar.notify_all();
+        }));
+
+        MessageStatus::Ok
+    }
+
+    fn stop_capture(&mut self) {
+        let Some(capture_thread) = self.capture_thread.take() else {
+            return;
+        };
+
+        self.stop.stop();
+
+        let (state, condvar) = &*self.state;
+        let mut state = state.lock().unwrap();
+        state.running = false;
+        condvar.notify_all();
+
+        let (state, _) = condvar.wait_timeout_while(state, STOP_TIMEOUT, |state| !state.finished).unwrap();
+        if state.finished {
+            drop(state);
+            capture_thread.join().unwrap();
+        } else {
+            println!("MicrophoneService: AudioSource didn't return within {:?}, leaving the capture thread behind", STOP_TIMEOUT);
+        }
+    }
+}
+
+impl Drop for MicrophoneService {
+    fn drop(&mut self) {
+        self.stop_capture();
+    }
 }
 
 impl Service for MicrophoneService {
@@ -24,13 +245,7 @@ impl Service for MicrophoneService {
 
         let mut media_source = MediaSourceService::new();
         media_source.set_type(MediaCodecType::MediaCodecAudioPCM);
-
-        let mut audio_config = AudioConfiguration::new();
-        audio_config.sample_rate = Some (16000);
-        audio_config.number_of_bits = Some(16);
-        audio_config.number_of_channels = Some(1);
-
-        media_source.audio_config = Some(audio_config).into();
+        media_source.audio_config = Some(self.config.to_protobuf()).into();
 
         service.media_source_service = Some(media_source).into();
 
@@ -38,6 +253,198 @@ impl Service for MicrophoneService {
     }
 
     fn handle_message(&mut self, message: Message) {
-        println!("Unsupported MicrophoneChannel: {} {} {} {} {}", message.channel, message.is_control, message.length, message.msg_type, hex::encode(&message.data));
+        match MediaMessageType::from_u16(message.msg_type) {
+            Some(MediaMessageType::MicRequest) => {
+                self.handle_microphone_request(message);
+            }
+            Some(MediaMessageType::Ack) => {
+                self.handle_media_ack(message);
+            }
+            _ => {
+                println!("Unsupported MicrophoneChannel: {} {} {} {} {}", message.channel, message.is_control, message.length, message.msg_type, hex::encode(&message.data));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread::sleep;
+
+    // Returns silence right away, or blocks until stopped
+    struct TestSource {
+        reads: Arc<AtomicUsize>,
+        blocking: bool,
+        stop: Option<StopSignal>,
+    }
+
+    impl AudioSource for TestSource {
+        fn open(&mut self, _config: &AudioConfig, _options: &MicrophoneOptions, stop: &StopSignal) {
+            self.stop = Some(stop.clone());
+        }
+
+        fn read(&mut self, buf: &mut [u8]) -> usize {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+
+            if self.blocking {
+                while !self.stop.as_ref().unwrap().is_stopped() {
+                    sleep(Duration::from_millis(5));
+                }
+            }
+
+            buf.fill(0);
+            buf.len()
+        }
+    }
+
+    // Ignores the stop signal, like a read stuck on a broken device
+    struct StuckSource;
+
+    impl AudioSource for StuckSource {
+        fn read(&mut self, _buf: &mut [u8]) -> usize {
+            sleep(Duration::from_secs(5));
+            0
+        }
+    }
+
+    fn microphone() -> (MicrophoneService, Arc<AtomicUsize>, Arc<ConnectionContext>) {
+        let context = Arc::new(ConnectionContext::new());
+        let reads = Arc::new(AtomicUsize::new(0));
+
+        let source = TestSource { reads: Arc::clone(&reads), blocking: false, stop: None };
+        let service = MicrophoneService::new(AudioConfig::new(16000, 16, 1), Arc::clone(&context)).with_source(source);
+
+        (service, reads, context)
+    }
+
+    fn request(open: bool, max_unacked: i32) -> Message {
+        let mut request = MicrophoneRequest::new();
+        request.set_open(open);
+        request.set_max_unacked(max_unacked);
+
+        Message::new_with_protobuf_message(5, false, request, MediaMessageType::MicRequest as u16)
+    }
+
+    fn ack(session_id: i32, count: u32) -> Message {
+        let mut ack = media::Ack::new();
+        ack.set_session_id(session_id);
+        ack.set_ack(count);
+
+        Message::new_with_protobuf_message(5, false, ack, MediaMessageType::Ack as u16)
+    }
+
+    // Takes the responses (status, session) and the number of media frames sent since the last call
+    fn sent(context: &ConnectionContext) -> (Vec<(i32, u32)>, usize) {
+        let mut responses = vec![];
+        let mut frames = 0;
+
+        for (message, _) in context.commands().lock().unwrap().messages_to_send() {
+            match MediaMessageType::from_u16(message.msg_type) {
+                Some(MediaMessageType::MicResponse) => {
+                    let response: MicrophoneResponse = message.to_protobuf_message();
+                    responses.push((response.status(), response.session_id()));
+                }
+                Some(MediaMessageType::MediaData) => {
+                    // Timestamp and 20 ms of 16 kHz mono
+                    assert_eq!(message.data.len(), 8 + 640);
+                    frames += 1;
+                }
+                _ => panic!("unexpected message {}", message.msg_type),
+            }
+        }
+
+        (responses, frames)
+    }
+
+    // Gives the capture thread time to run into the unacked limit
+    fn settle() {
+        sleep(Duration::from_millis(100));
+    }
+
+    #[test]
+    fn without_source() {
+        let context = Arc::new(ConnectionContext::new());
+        let mut service = MicrophoneService::new(AudioConfig::new(16000, 16, 1), Arc::clone(&context));
+
+        service.handle_message(request(true, 1));
+        assert_eq!(sent(&context), (vec![(MessageStatus::Error as i32, 0)], 0));
+    }
+
+    #[test]
+    fn max_unacked() {
+        let (mut service, _, context) = microphone();
+
+        service.handle_message(request(true, 2));
+        settle();
+        assert_eq!(sent(&context), (vec![(MessageStatus::Ok as i32, 1)], 2));
+
+        service.handle_message(ack(1, 1));
+        settle();
+        assert_eq!(sent(&context).1, 1);
+
+        service.handle_message(ack(1, 2));
+        settle();
+        assert_eq!(sent(&context).1, 2);
+
+        service.handle_message(request(false, 0));
+        assert_eq!(sent(&context).0, [(MessageStatus::Ok as i32, 1)]);
+    }
+
+    #[test]
+    fn acks_of_other_sessions_are_ignored() {
+        let (mut service, _, context) = microphone();
+
+        service.handle_message(request(true, 1));
+        settle();
+        service.handle_message(request(false, 0));
+        service.handle_message(request(true, 1));
+        settle();
+        let (responses, _) = sent(&context);
+        assert_eq!(responses.last(), Some(&(MessageStatus::Ok as i32, 2)));
+
+        // An ack of the first session doesn't release the second one
+        service.handle_message(ack(1, 1));
+        settle();
+        assert_eq!(sent(&context).1, 0);
+
+        service.handle_message(ack(2, 1));
+        settle();
+        assert_eq!(sent(&context).1, 1);
+    }
+
+    #[test]
+    fn stop_interrupts_blocking_read() {
+        let context = Arc::new(ConnectionContext::new());
+        let reads = Arc::new(AtomicUsize::new(0));
+        let source = TestSource { reads: Arc::clone(&reads), blocking: true, stop: None };
+        let mut service = MicrophoneService::new(AudioConfig::new(16000, 16, 1), Arc::clone(&context)).with_source(source);
+
+        service.handle_message(request(true, 1));
+        settle();
+        assert_eq!(reads.load(Ordering::Relaxed), 1);
+
+        let start = Instant::now();
+        service.handle_message(request(false, 0));
+        assert!(start.elapsed() < STOP_TIMEOUT);
+        assert!(service.capture_thread.is_none());
+
+        // The frame read while stopping is dropped
+        assert_eq!(sent(&context).1, 0);
+    }
+
+    #[test]
+    fn stuck_source_is_left_behind() {
+        let context = Arc::new(ConnectionContext::new());
+        let mut service = MicrophoneService::new(AudioConfig::new(16000, 16, 1), Arc::clone(&context)).with_source(StuckSource);
+
+        service.handle_message(request(true, 1));
+        settle();
+
+        let start = Instant::now();
+        drop(service);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= STOP_TIMEOUT && elapsed < STOP_TIMEOUT * 3, "{:?}", elapsed);
     }
 }
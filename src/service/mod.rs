@@ -3,11 +3,14 @@ pub mod control;
 pub mod input;
 pub mod media_play_back;
 pub mod microphone;
+pub mod navigation;
+pub mod phone_status;
 pub mod sensor;
 pub mod video;
 
 use crate::data::{MessageRequest, ServiceMessageHandler, ServiceMessageHandlerArg};
 use crate::message::Message;
+use std::sync::mpsc::Sender;
 
 macro_rules! factory_add_handler (($func_name:ident, $handler_name:ident) => {
     pub fn $func_name<Args, H>(&mut self, handler: H)
@@ -25,6 +28,26 @@ macro_rules! factory_add_handler (($func_name:ident, $handler_name:ident) => {
     }
 });
 
+// Optional receiver of the events of a service, the events are dropped once it is gone
+pub(crate) struct EventSender<T>(Option<Sender<T>>);
+
+impl<T> EventSender<T> {
+    pub(crate) fn none() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn set(&mut self, sender: Sender<T>) {
+        self.0 = Some(sender);
+    }
+
+    pub(crate) fn send(&mut self, event: T) {
+        if let Some(sender) = &self.0
+            && sender.send(event).is_err() {
+            self.0 = None;
+        }
+    }
+}
+
 pub trait Service {
     fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service;
 
@@ -33,6 +56,9 @@ pub trait Service {
     fn on_channel_open(&mut self) {
         // TODO
     }
+
+    // Called once when the service is added to a connection, with the channel it will use
+    fn on_add(&mut self, _channel_id: u8) {}
 }
 
 pub struct MediaSinkServiceConfig {}
@@ -80,4 +106,26 @@ pub enum ServiceType {
     Navigation,
     MediaPlayback,
     Sensors,
+    PhoneStatus,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn event_sender() {
+        let mut event_sender = EventSender::none();
+        event_sender.send(1);
+
+        let (sender, receiver) = channel();
+        event_sender.set(sender);
+        event_sender.send(2);
+        assert_eq!(receiver.try_recv(), Ok(2));
+
+        drop(receiver);
+        event_sender.send(3);
+        assert!(event_sender.0.is_none());
+    }
 }
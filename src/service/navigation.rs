@@ -0,0 +1,167 @@
+use crate::message::{Message, NavigationMessageType};
+use crate::protobuf::control::service::navigation_status_service::ImageOptions;
+use crate::protobuf::control::service::NavigationStatusService;
+use crate::protobuf::navigation::{NextTurnDetail, NextTurnDistanceEvent};
+use crate::service::{EventSender, Service};
+use protobuf::Message as ProtoMessage;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+pub use crate::protobuf::navigation::next_turn_detail::NextEvent as TurnEvent;
+pub use crate::protobuf::navigation::next_turn_detail::Side as TurnSide;
+
+// Values of NavigationStatusService.type
+const NAVIGATION_TYPE_IMAGE: u32 = 1;
+const NAVIGATION_TYPE_ENUM: u32 = 2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct NavigationImageOptions {
+    pub width: i32,
+    pub height: i32,
+    pub color_depth_bits: i32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct NavigationServiceConfig {
+    // The phone doesn't send updates more often than this
+    pub minimum_interval: Duration,
+    // Turn icons are only sent when set, otherwise the cluster has to draw `TurnEvent`s itself
+    pub image_options: Option<NavigationImageOptions>,
+}
+
+impl Default for NavigationServiceConfig {
+    fn default() -> Self {
+        Self {
+            minimum_interval: Duration::from_millis(500),
+            image_options: Some(NavigationImageOptions {
+                width: 256,
+                height: 256,
+                color_depth_bits: 16,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NextTurn {
+    pub road: String,
+    pub side: TurnSide,
+    pub event: TurnEvent,
+    // PNG as requested by `NavigationImageOptions`
+    pub image: Option<Vec<u8>>,
+    pub roundabout_exit: Option<u32>,
+    // Degrees
+    pub roundabout_angle: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NavigationEvent {
+    NextTurn(NextTurn),
+    // Meters and seconds to the next turn
+    Distance { distance: Option<u32>, time: Option<u32> },
+}
+
+pub struct NavigationService {
+    config: NavigationServiceConfig,
+    event_sender: EventSender<NavigationEvent>,
+}
+
+impl NavigationService {
+    pub fn new(config: NavigationServiceConfig) -> Self {
+        Self {
+            config,
+            event_sender: EventSender::none(),
+        }
+    }
+
+    pub fn with_event_sender(mut self, event_sender: Sender<NavigationEvent>) -> Self {
+        self.event_sender.set(event_sender);
+
+        self
+    }
+
+    fn send_event(&mut self, event: NavigationEvent) {
+        self.event_sender.send(event);
+    }
+
+    fn handle_next_turn_details(&mut self, message: Message) {
+        let data = match NextTurnDetail::parse_from_bytes(message.data.as_slice()) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("NavigationService: Invalid NextTurnDetail: {}", e);
+                return;
+            }
+        };
+
+        let next_turn = NextTurn {
+            road: data.road().to_owned(),
+            side: data.side(),
+            event: data.next_turn(),
+            image: data.turngraph.clone().filter(|image| !image.is_empty()),
+            roundabout_exit: data.trunnumer,
+            roundabout_angle: data.turnangel,
+        };
+
+        println!("NavigationService: {:?} {:?} onto {:?}", next_turn.event, next_turn.side, next_turn.road);
+
+        self.send_event(NavigationEvent::NextTurn(next_turn));
+    }
+
+    fn handle_next_turn_distance(&mut self, message: Message) {
+        let data = match NextTurnDistanceEvent::parse_from_bytes(message.data.as_slice()) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("NavigationService: Invalid NextTurnDistanceEvent: {}", e);
+                return;
+            }
+        };
+
+        self.send_event(NavigationEvent::Distance {
+            distance: data.distance,
+            time: data.time,
+        });
+    }
+}
+
+impl Service for NavigationService {
+    fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service {
+        let mut service = crate::protobuf::control::Service::new();
+        service.id = Some(channel_id as u32);
+
+        let mut navigation_status = NavigationStatusService::new();
+        navigation_status.minimum_interval_ms = Some(self.config.minimum_interval.as_millis() as u32);
+
+        match self.config.image_options {
+            Some(options) => {
+                let mut image_options = ImageOptions::new();
+                image_options.width = Some(options.width);
+                image_options.height = Some(options.height);
+                image_options.color_depth_bits = Some(options.color_depth_bits);
+
+                navigation_status.type_ = Some(NAVIGATION_TYPE_IMAGE);
+                navigation_status.image_options = Some(image_options).into();
+            }
+            None => {
+                navigation_status.type_ = Some(NAVIGATION_TYPE_ENUM);
+            }
+        }
+
+        service.navigation_status_service = Some(navigation_status).into();
+
+        service
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match NavigationMessageType::from_u16(message.msg_type) {
+            Some(NavigationMessageType::NextTurnDetails) if !message.is_control => {
+                self.handle_next_turn_details(message);
+            }
+            Some(NavigationMessageType::NextTurnDistanceAndTime) if !message.is_control => {
+                self.handle_next_turn_distance(message);
+            }
+            _ => {
+                println!("Unsupported NavigationChannel: {} {} {} {} {}", message.channel, message.is_control, message.length, message.msg_type, hex::encode(&message.data));
+            }
+        }
+    }
+}
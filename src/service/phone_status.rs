@@ -0,0 +1,338 @@
+use crate::connection::ConnectionContext;
+use crate::message::{Message, PhoneStatusMessageType};
+use crate::protobuf::control::service::{PhoneStatusService as PhoneStatusProto, PhoneStatus_Input};
+use crate::service::input::{InputHandle, KeyCode};
+use crate::service::{EventSender, Service};
+use protobuf::Message as ProtoMessage;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub use crate::protobuf::control::service::PhoneStatus_State as CallState;
+
+// The keycodes used for call actions, they have to be part of `InputServiceConfig::keycodes`
+pub const CALL_KEYCODES: [KeyCode; 2] = [KeyCode::KeycodeCall, KeyCode::KeycodeEndCall];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub state: Option<CallState>,
+    pub duration: Option<Duration>,
+    pub caller_number: Option<String>,
+    // Contact name
+    pub caller_id: Option<String>,
+    // E.g. "Mobile", "Work"
+    pub caller_number_type: Option<String>,
+    // Encoded contact picture
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+impl Call {
+    fn is_identified(&self) -> bool {
+        self.caller_number.is_some() || self.caller_id.is_some()
+    }
+
+    // State of this call (at `index` in the current list) in the previous update. Calls are
+    // matched by number and contact, calls with a hidden number by their position.
+    fn previous_state(&self, index: usize, previous: &[Call]) -> Option<CallState> {
+        let previous = if self.is_identified() {
+            previous.iter().find(|previous| previous.caller_number == self.caller_number && previous.caller_id == self.caller_id)
+        } else {
+            previous.get(index).filter(|previous| !previous.is_identified())
+        };
+
+        previous.and_then(|previous| previous.state)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PhoneStatusEvent {
+    // Sent when a call enters the state
+    Incoming(Call),
+    InCall(Call),
+    OnHold(Call),
+    HangingUp(Call),
+    Muted(Call),
+    Conferenced(Call),
+    // The last call ended
+    Idle,
+    SignalStrength(u32),
+    // Every status update, including the call durations counting up
+    Calls(Vec<Call>),
+}
+
+#[derive(Default)]
+struct PhoneState {
+    calls: Vec<Call>,
+    signal_strength: Option<u32>,
+}
+
+// Queries the calls and sends call actions from anywhere in the application
+#[derive(Clone)]
+pub struct PhoneStatusHandle {
+    channel_id: Arc<AtomicU8>,
+    state: Arc<Mutex<PhoneState>>,
+    input: Option<InputHandle>,
+    context: Arc<ConnectionContext>,
+}
+
+impl PhoneStatusHandle {
+    pub fn calls(&self) -> Vec<Call> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    pub fn signal_strength(&self) -> Option<u32> {
+        self.state.lock().unwrap().signal_strength
+    }
+
+    pub fn incoming_call(&self) -> Option<Call> {
+        self.calls().into_iter().find(|call| call.state == Some(CallState::Incoming))
+    }
+
+    // Accepts the incoming call
+    pub fn answer(&self) {
+        self.press(KeyCode::KeycodeCall);
+    }
+
+    // Rejects the incoming call or ends the active one
+    pub fn hang_up(&self) {
+        self.press(KeyCode::KeycodeEndCall);
+    }
+
+    // Asks the phone to call a number (or a contact by `caller_id`)
+    pub fn dial(&self, caller_number: Option<&str>, caller_id: Option<&str>) {
+        let channel_id = self.channel_id.load(Ordering::Relaxed);
+        if channel_id == 0 {
+            println!("PhoneStatusService: Not registered yet, dropping input");
+            return;
+        }
+
+        let mut input = PhoneStatus_Input::new();
+        input.caller_number = caller_number.map(str::to_owned);
+        input.caller_id = caller_id.map(str::to_owned);
+
+        let mut commands = self.context.commands().lock().unwrap();
+        commands.send_message(Message::new_with_protobuf_message(
+            channel_id,
+            false,
+            input,
+            PhoneStatusMessageType::PhoneStatusInput as u16
+        ), true);
+    }
+
+    fn press(&self, keycode: KeyCode) {
+        match &self.input {
+            Some(input) => input.key_press(keycode, 0),
+            None => println!("PhoneStatusService: No input handle for {:?}", keycode),
+        }
+    }
+}
+
+pub struct PhoneStatusService {
+    channel_id: Arc<AtomicU8>,
+    state: Arc<Mutex<PhoneState>>,
+    input: Option<InputHandle>,
+    event_sender: EventSender<PhoneStatusEvent>,
+    context: Arc<ConnectionContext>,
+}
+
+impl PhoneStatusService {
+    pub fn new(context: Arc<ConnectionContext>) -> Self {
+        Self {
+            channel_id: Arc::new(AtomicU8::new(0)),
+            state: Arc::new(Mutex::new(PhoneState::default())),
+            input: None,
+            event_sender: EventSender::none(),
+            context,
+        }
+    }
+
+    // Answer and hang up are sent as key events through the input service
+    pub fn with_input(mut self, input: InputHandle) -> Self {
+        self.input = Some(input);
+
+        self
+    }
+
+    pub fn with_event_sender(mut self, event_sender: Sender<PhoneStatusEvent>) -> Self {
+        self.event_sender.set(event_sender);
+
+        self
+    }
+
+    pub fn handle(&self) -> PhoneStatusHandle {
+        PhoneStatusHandle {
+            channel_id: Arc::clone(&self.channel_id),
+            state: Arc::clone(&self.state),
+            input: self.input.clone(),
+            context: Arc::clone(&self.context),
+        }
+    }
+
+    fn send_event(&mut self, event: PhoneStatusEvent) {
+        self.event_sender.send(event);
+    }
+
+    fn handle_phone_status(&mut self, message: Message) {
+        let data = PhoneStatusProto::parse_from_bytes(message.data.as_slice()).unwrap();
+
+        let calls: Vec<Call> = data.calls.into_iter()
+            .map(|call| Call {
+                state: call.state.and_then(|state| state.enum_value().ok()),
+                duration: call.call_duration_seconds.map(|seconds| Duration::from_secs(seconds as u64)),
+                caller_number: call.caller_number,
+                caller_id: call.caller_id,
+                caller_number_type: call.caller_number_type,
+                thumbnail: call.thumbnail.filter(|thumbnail| !thumbnail.is_empty()),
+            })
+            .collect();
+
+        let (previous, signal_strength_changed) = {
+            let mut state = self.state.lock().unwrap();
+
+            let previous = std::mem::replace(&mut state.calls, calls.clone());
+
+            let signal_strength_changed = data.signal_strength.is_some() && data.signal_strength != state.signal_strength;
+            if signal_strength_changed {
+                state.signal_strength = data.signal_strength;
+            }
+
+            (previous, signal_strength_changed)
+        };
+
+        for (index, call) in calls.iter().enumerate() {
+            if call.state == call.previous_state(index, &previous) {
+                continue;
+            }
+
+            println!("PhoneStatusService: {:?} {:?} {:?}", call.state, call.caller_id, call.caller_number);
+
+            let event = match call.state {
+                Some(CallState::Incoming) => PhoneStatusEvent::Incoming(call.clone()),
+                Some(CallState::InCall) => PhoneStatusEvent::InCall(call.clone()),
+                Some(CallState::OnHold) => PhoneStatusEvent::OnHold(call.clone()),
+                Some(CallState::HangingUp) => PhoneStatusEvent::HangingUp(call.clone()),
+                Some(CallState::Muted) => PhoneStatusEvent::Muted(call.clone()),
+                Some(CallState::Conferenced) => PhoneStatusEvent::Conferenced(call.clone()),
+                None => continue,
+            };
+
+            self.send_event(event);
+        }
+
+        if calls.is_empty() && !previous.is_empty() {
+            println!("PhoneStatusService: No calls");
+
+            self.send_event(PhoneStatusEvent::Idle);
+        }
+
+        if signal_strength_changed && let Some(signal_strength) = data.signal_strength {
+            self.send_event(PhoneStatusEvent::SignalStrength(signal_strength));
+        }
+
+        self.send_event(PhoneStatusEvent::Calls(calls));
+    }
+}
+
+impl Service for PhoneStatusService {
+    fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service {
+        self.channel_id.store(channel_id, Ordering::Relaxed);
+
+        let mut service = crate::protobuf::control::Service::new();
+        service.id = Some(channel_id as u32);
+
+        service.phone_status_service = Some(PhoneStatusProto::new()).into();
+
+        service
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match PhoneStatusMessageType::from_u16(message.msg_type) {
+            Some(PhoneStatusMessageType::PhoneStatus) if !message.is_control => {
+                self.handle_phone_status(message);
+            }
+            _ => {
+                println!("Unsupported PhoneStatusChannel: {} {} {} {} {}", message.channel, message.is_control, message.length, message.msg_type, hex::encode(&message.data));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protobuf::control::service::PhoneStatus_Call;
+    use std::sync::mpsc::{channel, Receiver};
+
+    fn service() -> (PhoneStatusService, Receiver<PhoneStatusEvent>) {
+        let (event_sender, events) = channel();
+
+        (PhoneStatusService::new(Arc::new(ConnectionContext::new())).with_event_sender(event_sender), events)
+    }
+
+    fn update(service: &mut PhoneStatusService, calls: &[(CallState, Option<&str>)]) {
+        let mut data = PhoneStatusProto::new();
+        for (state, caller_number) in calls {
+            let mut call = PhoneStatus_Call::new();
+            call.set_state(*state);
+            call.caller_number = caller_number.map(str::to_owned);
+            data.calls.push(call);
+        }
+
+        service.handle_message(Message::new_with_protobuf_message(1, false, data, PhoneStatusMessageType::PhoneStatus as u16));
+    }
+
+    // State events without the `Calls` updates
+    fn events(events: &Receiver<PhoneStatusEvent>) -> Vec<(&'static str, Option<String>)> {
+        events.try_iter()
+            .filter_map(|event| match event {
+                PhoneStatusEvent::Incoming(call) => Some(("incoming", call.caller_number)),
+                PhoneStatusEvent::InCall(call) => Some(("in call", call.caller_number)),
+                PhoneStatusEvent::OnHold(call) => Some(("on hold", call.caller_number)),
+                PhoneStatusEvent::Idle => Some(("idle", None)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calls_by_number() {
+        let (mut service, receiver) = service();
+
+        update(&mut service, &[(CallState::Incoming, Some("1"))]);
+        update(&mut service, &[(CallState::InCall, Some("1"))]);
+        update(&mut service, &[(CallState::InCall, Some("1"))]);
+        update(&mut service, &[(CallState::Incoming, Some("2")), (CallState::InCall, Some("1"))]);
+        update(&mut service, &[(CallState::OnHold, Some("1")), (CallState::InCall, Some("2"))]);
+        update(&mut service, &[]);
+
+        let number = |number: &str| Some(number.to_owned());
+        assert_eq!(events(&receiver), vec![
+            ("incoming", number("1")),
+            ("in call", number("1")),
+            ("incoming", number("2")),
+            ("on hold", number("1")),
+            ("in call", number("2")),
+            ("idle", None),
+        ]);
+    }
+
+    #[test]
+    fn hidden_numbers() {
+        let (mut service, receiver) = service();
+
+        update(&mut service, &[(CallState::InCall, None)]);
+        update(&mut service, &[(CallState::InCall, None), (CallState::Incoming, None)]);
+        update(&mut service, &[(CallState::OnHold, None), (CallState::InCall, None)]);
+        update(&mut service, &[(CallState::OnHold, None), (CallState::InCall, None)]);
+        update(&mut service, &[]);
+
+        assert_eq!(events(&receiver), vec![
+            ("in call", None),
+            ("incoming", None),
+            ("on hold", None),
+            ("in call", None),
+            ("idle", None),
+        ]);
+    }
+}
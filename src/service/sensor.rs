@@ -4,30 +4,535 @@ use crate::protobuf::common::MessageStatus;
 use crate::protobuf::control::service::sensor_source_service::Sensor;
 use crate::protobuf::control::service::SensorSourceService;
 use crate::protobuf::sensors;
-use crate::protobuf::sensors::sensor_batch::{driving_status_data, DrivingStatusData};
-use crate::protobuf::sensors::{SensorRequest, SensorType};
+use crate::protobuf::sensors::sensor_batch;
+use crate::protobuf::sensors::SensorRequest;
 use crate::service::Service;
 use protobuf::Message as ProtoMessage;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub use crate::protobuf::sensors::sensor_batch::driving_status_data::Status as DrivingStatus;
+pub use crate::protobuf::sensors::sensor_batch::gear_data::Gear;
+pub use crate::protobuf::sensors::sensor_batch::light_data::{HeadlightState, TurnIndicatorState};
+pub use crate::protobuf::sensors::SensorType;
+
+pub struct SensorServiceConfig {
+    // Sensors the head unit provides, only these are advertised and accepted by the SensorHandle
+    pub sensors: Vec<SensorType>,
+}
+
+impl Default for SensorServiceConfig {
+    fn default() -> Self {
+        Self {
+            sensors: vec![SensorType::DrivingStatus],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocationReading {
+    // Time of the fix in milliseconds since the unix epoch
+    pub timestamp: u64,
+    pub latitude: f64,
+    pub longitude: f64,
+    // Meters
+    pub accuracy: f64,
+    // Meters above sea level
+    pub altitude: Option<f64>,
+    // m/s
+    pub speed: Option<f64>,
+    // Degrees clockwise from north
+    pub bearing: Option<f64>,
+}
+
+impl LocationReading {
+    pub fn new(latitude: f64, longitude: f64, accuracy: f64) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        Self {
+            timestamp,
+            latitude,
+            longitude,
+            accuracy,
+            altitude: None,
+            speed: None,
+            bearing: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GpsSatellite {
+    pub prn: i32,
+    // dB-Hz
+    pub snr: f64,
+    pub used_in_fix: bool,
+    // Degrees
+    pub azimuth: f64,
+    pub elevation: f64,
+}
+
+// A single reading in SI-ish units, converted to the fixed point representation of
+// sensors.proto when sent
+#[derive(Clone, Debug, PartialEq)]
+pub enum SensorReading {
+    Location(LocationReading),
+    // Degrees
+    Compass { bearing: f64, pitch: f64, roll: f64 },
+    // m/s
+    Speed { speed: f64, cruise_engaged: Option<bool>, cruise_set_speed: Option<bool> },
+    Rpm(i32),
+    // Kilometers
+    Odometer { total: Option<f64>, trip: Option<f64> },
+    // Level in percent, range in kilometers
+    Fuel { level: i32, range: Option<i32>, low_fuel: Option<bool> },
+    ParkingBrake(bool),
+    Gear(Gear),
+    Diagnostics(Vec<u8>),
+    Night(bool),
+    // °C, kPa
+    Environment { temperature: Option<f64>, pressure: Option<f64>, rain: Option<i32> },
+    // °C
+    Hvac { target_temperature: Option<f64>, current_temperature: Option<f64> },
+    // Bitmask of `DrivingStatus` values
+    DrivingStatus(i32),
+    DeadReckoning { steering_angle: Option<i32>, wheel_speed: Option<i32> },
+    Passenger(bool),
+    Door { hood_open: bool, boot_open: bool, doors_open: Vec<bool> },
+    Light { headlight: Option<HeadlightState>, turn_indicator: Option<TurnIndicatorState>, hazard_light_on: Option<bool> },
+    // m/s²
+    Accelerometer { x: f64, y: f64, z: f64 },
+    // rad/s
+    Gyroscope { x: f64, y: f64, z: f64 },
+    GpsSatellites { in_use: i32, in_view: i32, satellites: Vec<GpsSatellite> },
+}
+
+fn e3(value: f64) -> i32 {
+    (value * 1e3).round() as i32
+}
+
+fn e6(value: f64) -> i32 {
+    (value * 1e6).round() as i32
+}
+
+fn e7(value: f64) -> i32 {
+    (value * 1e7).round() as i32
+}
+
+impl SensorReading {
+    pub fn sensor_type(&self) -> SensorType {
+        match self {
+            SensorReading::Location(_) => SensorType::Location,
+            SensorReading::Compass { .. } => SensorType::Compass,
+            SensorReading::Speed { .. } => SensorType::CarSpeed,
+            SensorReading::Rpm(_) => SensorType::RPM,
+            SensorReading::Odometer { .. } => SensorType::Odometer,
+            SensorReading::Fuel { .. } => SensorType::FuelLevel,
+            SensorReading::ParkingBrake(_) => SensorType::PARKING_BRAKE,
+            SensorReading::Gear(_) => SensorType::Gear,
+            SensorReading::Diagnostics(_) => SensorType::Diagnostics,
+            SensorReading::Night(_) => SensorType::Night,
+            SensorReading::Environment { .. } => SensorType::Environment,
+            SensorReading::Hvac { .. } => SensorType::HVAC,
+            SensorReading::DrivingStatus(_) => SensorType::DrivingStatus,
+            SensorReading::DeadReckoning { .. } => SensorType::DeadReckoning,
+            SensorReading::Passenger(_) => SensorType::Passenger,
+            SensorReading::Door { .. } => SensorType::Door,
+            SensorReading::Light { .. } => SensorType::Light,
+            SensorReading::Accelerometer { .. } => SensorType::Accel,
+            SensorReading::Gyroscope { .. } => SensorType::Gyro,
+            SensorReading::GpsSatellites { .. } => SensorType::GPS,
+        }
+    }
+
+    fn add_to_batch(&self, batch: &mut sensors::SensorBatch) {
+        match self {
+            SensorReading::Location(location) => {
+                let mut data = sensor_batch::LocationData::new();
+                data.timestamp = Some(location.timestamp);
+                data.latitude = Some(e7(location.latitude));
+                data.longitude = Some(e7(location.longitude));
+                data.accuracy = Some(e3(location.accuracy).max(0) as u32);
+                data.altitude = location.altitude.map(|altitude| (altitude * 1e2).round() as i32);
+                data.speed = location.speed.map(e3);
+                data.bearing = location.bearing.map(e6);
+
+                batch.location_data.push(data);
+            }
+            SensorReading::Compass { bearing, pitch, roll } => {
+                let mut data = sensor_batch::CompassData::new();
+                data.bearing_e6 = Some(e6(*bearing));
+                data.pitch_e6 = Some(e6(*pitch));
+                data.roll_e6 = Some(e6(*roll));
+
+                batch.compass_data.push(data);
+            }
+            SensorReading::Speed { speed, cruise_engaged, cruise_set_speed } => {
+                let mut data = sensor_batch::SpeedData::new();
+                // mm/s like the speed of LocationData, despite the field name
+                data.speed_e6 = Some(e3(*speed));
+                data.cruise_engaged = *cruise_engaged;
+                data.cruise_set_speed = *cruise_set_speed;
+
+                batch.speed_data.push(data);
+            }
+            SensorReading::Rpm(rpm) => {
+                let mut data = sensor_batch::RpmData::new();
+                data.rpm = Some(*rpm);
+
+                batch.rpm.push(data);
+            }
+            SensorReading::Odometer { total, trip } => {
+                let mut data = sensor_batch::OdometerData::new();
+                data.kms_el = total.map(|total| (total * 10.0).round() as i32);
+                data.trip_kms_el = trip.map(|trip| (trip * 10.0).round() as i32);
+
+                batch.odometer_data.push(data);
+            }
+            SensorReading::Fuel { level, range, low_fuel } => {
+                let mut data = sensor_batch::FuelData::new();
+                data.fuel_level = Some(*level);
+                data.range = *range;
+                data.low_fuel = *low_fuel;
+
+                batch.fuel_data.push(data);
+            }
+            SensorReading::ParkingBrake(engaged) => {
+                let mut data = sensor_batch::ParkingBrakeData::new();
+                data.is_engaged = Some(*engaged);
+
+                batch.parking_brake_data.push(data);
+            }
+            SensorReading::Gear(gear) => {
+                let mut data = sensor_batch::GearData::new();
+                data.set_gear(*gear);
+
+                batch.gear_data.push(data);
+            }
+            SensorReading::Diagnostics(diagnostics) => {
+                let mut data = sensor_batch::DiagnosticsData::new();
+                data.diagnostics_byte = Some(diagnostics.clone());
+
+                batch.diagnostics_data.push(data);
+            }
+            SensorReading::Night(night) => {
+                let mut data = sensor_batch::NightData::new();
+                data.is_night_mode = Some(*night);
+
+                batch.night_mode.push(data);
+            }
+            SensorReading::Environment { temperature, pressure, rain } => {
+                let mut data = sensor_batch::EnvironmentData::new();
+                data.temperature_e3 = temperature.map(e3);
+                data.pressure_e3 = pressure.map(e3);
+                data.rain = *rain;
+
+                batch.environment_data.push(data);
+            }
+            SensorReading::Hvac { target_temperature, current_temperature } => {
+                let mut data = sensor_batch::HvacData::new();
+                data.target_temperature_e3 = target_temperature.map(e3);
+                data.current_temperature_e3 = current_temperature.map(e3);
+
+                batch.hvac_data.push(data);
+            }
+            SensorReading::DrivingStatus(status) => {
+                let mut data = sensor_batch::DrivingStatusData::new();
+                data.set_status(*status);
+
+                batch.driving_status.push(data);
+            }
+            SensorReading::DeadReckoning { steering_angle, wheel_speed } => {
+                let mut data = sensor_batch::DeadReckoningData::new();
+                data.steering_angel = *steering_angle;
+                data.wheel_speed = *wheel_speed;
+
+                batch.dead_reckoning.push(data);
+            }
+            SensorReading::Passenger(present) => {
+                let mut data = sensor_batch::PassengerData::new();
+                data.passenger_present = Some(*present);
+
+                batch.passenger_data.push(data);
+            }
+            SensorReading::Door { hood_open, boot_open, doors_open } => {
+                let mut data = sensor_batch::DoorData::new();
+                data.hood_open = Some(*hood_open);
+                data.boot_open = Some(*boot_open);
+                data.door_open = doors_open.clone();
+
+                batch.door_data.push(data);
+            }
+            SensorReading::Light { headlight, turn_indicator, hazard_light_on } => {
+                let mut data = sensor_batch::LightData::new();
+                if let Some(headlight) = headlight {
+                    data.set_headlight(*headlight);
+                }
+                if let Some(turn_indicator) = turn_indicator {
+                    data.set_turn_indicator(*turn_indicator);
+                }
+                data.hazard_light_on = *hazard_light_on;
+
+                batch.light_data.push(data);
+            }
+            SensorReading::Accelerometer { x, y, z } => {
+                let mut data = sensor_batch::AccelerometerData::new();
+                data.acceleration_x_e3 = Some(e3(*x));
+                data.acceleration_y_e3 = Some(e3(*y));
+                data.acceleration_z_e3 = Some(e3(*z));
+
+                batch.accel_data.push(data);
+            }
+            SensorReading::Gyroscope { x, y, z } => {
+                let mut data = sensor_batch::GyroscopeData::new();
+                data.rotation_speed_x_e3 = Some(e3(*x));
+                data.rotation_speed_y_e3 = Some(e3(*y));
+                data.rotation_speed_z_e3 = Some(e3(*z));
+
+                batch.gyro_data.push(data);
+            }
+            SensorReading::GpsSatellites { in_use, in_view, satellites } => {
+                let mut data = sensor_batch::GpsSatelliteData::new();
+                data.number_in_use = Some(*in_use);
+                data.number_in_view = Some(*in_view);
+
+                for satellite in satellites {
+                    let mut satellite_data = sensor_batch::gps_satellite_data::GpsSatellite::new();
+                    satellite_data.prn = Some(satellite.prn);
+                    satellite_data.snr_e3 = Some(e3(satellite.snr));
+                    satellite_data.used_in_fix = Some(satellite.used_in_fix);
+                    satellite_data.azimuth_e3 = Some(e3(satellite.azimuth));
+                    satellite_data.elevation_e3 = Some(e3(satellite.elevation));
+
+                    data.satellites.push(satellite_data);
+                }
+
+                batch.gps_satellite_data.push(data);
+            }
+        }
+    }
+}
+
+struct Subscription {
+    min_update_period: Duration,
+    last_sent: Option<Instant>,
+    // Latest reading that arrived within `min_update_period`, sent once it passed
+    pending: Option<SensorReading>,
+}
+
+impl Subscription {
+    fn due(&self, now: Instant) -> Option<Instant> {
+        self.last_sent.map(|last_sent| last_sent + self.min_update_period).filter(|due| *due > now)
+    }
+}
+
+struct SensorState {
+    sensors: Vec<SensorType>,
+    // Last reading per sensor, sent when the phone starts the sensor
+    last: BTreeMap<i32, SensorReading>,
+    // Sensors the phone started, readings of other sensors are only kept in `last`
+    subscriptions: BTreeMap<i32, Subscription>,
+    running: bool,
+}
+
+impl SensorState {
+    // Moves the pending readings that are due into `batch`, returns when the next one is due
+    fn flush_pending(&mut self, now: Instant, batch: &mut sensors::SensorBatch) -> Option<Instant> {
+        let mut next_due: Option<Instant> = None;
+
+        for subscription in self.subscriptions.values_mut() {
+            if subscription.pending.is_none() {
+                continue;
+            }
+
+            match subscription.due(now) {
+                Some(due) => {
+                    next_due = Some(next_due.map_or(due, |next_due| next_due.min(due)));
+                }
+                None => {
+                    subscription.pending.take().unwrap().add_to_batch(batch);
+                    subscription.last_sent = Some(now);
+                }
+            }
+        }
+
+        next_due
+    }
+}
+
+fn send_batch(context: &ConnectionContext, channel_id: u8, batch: sensors::SensorBatch) {
+    let mut commands = context.commands().lock().unwrap();
+    commands.send_message(Message::new_with_protobuf_message(
+        channel_id,
+        false,
+        batch,
+        SensorsMessageType::Event as u16
+    ), true);
+}
+
+// Pushes readings of the head unit sensors to the phone. Readings of sensors the phone
+// didn't start are held back, readings arriving faster than the requested update period
+// are coalesced so only the latest one is sent.
+#[derive(Clone)]
+pub struct SensorHandle {
+    channel_id: Arc<AtomicU8>,
+    state: Arc<(Mutex<SensorState>, Condvar)>,
+    context: Arc<ConnectionContext>,
+}
+
+impl SensorHandle {
+    pub fn push(&self, reading: SensorReading) {
+        self.push_batch(vec![reading]);
+    }
+
+    // Readings pushed together end up in the same SensorBatch
+    pub fn push_batch(&self, readings: Vec<SensorReading>) {
+        let mut batch = sensors::SensorBatch::new();
+        let mut empty = true;
+        let mut deferred = false;
+
+        {
+            let (state, condvar) = &*self.state;
+            let mut state = state.lock().unwrap();
+            let now = Instant::now();
+
+            for reading in readings {
+                let sensor_type = reading.sensor_type();
+                if !state.sensors.contains(&sensor_type) {
+                    println!("SensorService: {:?} is not provided, dropping reading", sensor_type);
+                    continue;
+                }
+
+                state.last.insert(sensor_type as i32, reading.clone());
+
+                let Some(subscription) = state.subscriptions.get_mut(&(sensor_type as i32)) else {
+                    continue;
+                };
+
+                if subscription.due(now).is_some() {
+                    subscription.pending = Some(reading);
+                    deferred = true;
+                } else {
+                    reading.add_to_batch(&mut batch);
+                    subscription.pending = None;
+                    subscription.last_sent = Some(now);
+                    empty = false;
+                }
+            }
+
+            if deferred {
+                condvar.notify_all();
+            }
+        }
+
+        let channel_id = self.channel_id.load(Ordering::Relaxed);
+        if empty || channel_id == 0 {
+            return;
+        }
+
+        send_batch(&self.context, channel_id, batch);
+    }
+}
 
 pub struct SensorService {
+    channel_id: Arc<AtomicU8>,
+    state: Arc<(Mutex<SensorState>, Condvar)>,
+    flush_thread: Option<JoinHandle<()>>,
     context: Arc<ConnectionContext>,
 }
 
 impl SensorService {
-    pub fn new(context: Arc<ConnectionContext>) -> Self {
-        Self {
+    pub fn new(config: SensorServiceConfig, context: Arc<ConnectionContext>) -> Self {
+        let mut last = BTreeMap::new();
+        if config.sensors.contains(&SensorType::DrivingStatus) {
+            last.insert(SensorType::DrivingStatus as i32, SensorReading::DrivingStatus(DrivingStatus::Unrestricted as i32));
+        }
+
+        let state = SensorState {
+            sensors: config.sensors,
+            last,
+            subscriptions: BTreeMap::new(),
+            running: true,
+        };
+
+        let mut service = Self {
+            channel_id: Arc::new(AtomicU8::new(0)),
+            state: Arc::new((Mutex::new(state), Condvar::new())),
+            flush_thread: None,
             context,
+        };
+        service.flush_thread = Some(service.spawn_flush_thread());
+
+        service
+    }
+
+    pub fn handle(&self) -> SensorHandle {
+        SensorHandle {
+            channel_id: Arc::clone(&self.channel_id),
+            state: Arc::clone(&self.state),
+            context: Arc::clone(&self.context),
         }
     }
 
+    // Sends coalesced readings once their update period passed
+    fn spawn_flush_thread(&self) -> JoinHandle<()> {
+        let state = Arc::clone(&self.state);
+        let channel_id = Arc::clone(&self.channel_id);
+        let context = Arc::clone(&self.context);
+
+        std::thread::spawn(move || {
+            loop {
+                let mut batch = sensors::SensorBatch::new();
+
+                {
+                    let (state, condvar) = &*state;
+                    let mut state = state.lock().unwrap();
+
+                    if !state.running {
+                        break;
+                    }
+
+                    let next_due = state.flush_pending(Instant::now(), &mut batch);
+
+                    if batch.compute_size() == 0 {
+                        let _state = match next_due {
+                            Some(next_due) => condvar.wait_timeout(state, next_due.saturating_duration_since(Instant::now())).unwrap().0,
+                            None => condvar.wait(state).unwrap(),
+                        };
+
+                        continue;
+                    }
+                }
+
+                let channel_id = channel_id.load(Ordering::Relaxed);
+                if channel_id != 0 {
+                    send_batch(&context, channel_id, batch);
+                }
+            }
+        })
+    }
+
     pub fn handle_sensor_start_request(&mut self, message: Message) {
         let data = SensorRequest::parse_from_bytes(message.data.as_slice()).unwrap();
 
-        //println!("SensorStartRequest: {:#?}", data.type_);
+        let sensor_type = data.type_();
+        let min_update_period = Duration::from_millis(data.min_update_period().max(0) as u64);
+
+        println!("SensorStartRequest: {:?} min_update_period {:?}", sensor_type, min_update_period);
+
+        let (state, _) = &*self.state;
+        let mut state = state.lock().unwrap();
+
+        let provided = state.sensors.contains(&sensor_type);
 
         let mut config = sensors::SensorResponse::new();
-        config.set_status(MessageStatus::Ok);
+        config.set_status(if provided { MessageStatus::Ok } else { MessageStatus::Error });
 
         let mut commands = self.context.commands().lock().unwrap();
         commands.send_message(Message::new_with_protobuf_message(
@@ -37,34 +542,68 @@ impl SensorService {
             SensorsMessageType::StartResponse as u16
         ), true);
 
+        if !provided {
+            println!("SensorService: {:?} is not provided", sensor_type);
+            return;
+        }
 
-        let mut config = sensors::SensorBatch::new();
-        let mut driving_status_data = DrivingStatusData::new();
-        driving_status_data.set_status(driving_status_data::Status::Unrestricted as i32);
-        config.driving_status.push(driving_status_data);
+        let last_reading = state.last.get(&(sensor_type as i32)).cloned();
 
-        commands.send_message(Message::new_with_protobuf_message(
-            message.channel,
-            false,
-            config,
-            SensorsMessageType::Event as u16
-        ), true);
+        let mut subscription = Subscription {
+            min_update_period,
+            last_sent: None,
+            pending: None,
+        };
+
+        if let Some(reading) = last_reading {
+            let mut batch = sensors::SensorBatch::new();
+            reading.add_to_batch(&mut batch);
+            subscription.last_sent = Some(Instant::now());
+
+            commands.send_message(Message::new_with_protobuf_message(
+                message.channel,
+                false,
+                batch,
+                SensorsMessageType::Event as u16
+            ), true);
+        }
+
+        state.subscriptions.insert(sensor_type as i32, subscription);
+    }
+}
+
+impl Drop for SensorService {
+    fn drop(&mut self) {
+        {
+            let (state, condvar) = &*self.state;
+            state.lock().unwrap().running = false;
+            condvar.notify_all();
+        }
+
+        if let Some(flush_thread) = self.flush_thread.take() {
+            flush_thread.join().unwrap();
+        }
     }
 }
 
 impl Service for SensorService {
     fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service {
+        self.channel_id.store(channel_id, Ordering::Relaxed);
+
         let mut service = crate::protobuf::control::Service::new();
         service.id = Some(channel_id as u32);
 
-        let mut sensor = Sensor::new();
-        sensor.set_type(SensorType::DrivingStatus);
-
         let mut sensor_source = SensorSourceService::new();
-        sensor_source.sensors.push(sensor);
+
+        for sensor_type in &self.state.0.lock().unwrap().sensors {
+            let mut sensor = Sensor::new();
+            sensor.set_type(*sensor_type);
+
+            sensor_source.sensors.push(sensor);
+        }
 
         service.sensor_source_service = Some(sensor_source).into();
-        
+
         service
     }
 
@@ -79,3 +618,185 @@ impl Service for SensorService {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    // A SensorService registered on channel 2
+    pub(crate) fn sensor_service(sensors: &[SensorType]) -> (SensorService, Arc<ConnectionContext>) {
+        let context = Arc::new(ConnectionContext::new());
+
+        let config = SensorServiceConfig { sensors: sensors.to_vec() };
+        let service = SensorService::new(config, Arc::clone(&context));
+        service.protobuf_descriptor(2);
+
+        (service, context)
+    }
+
+    // Latest reading pushed for `sensor_type`, whether the phone started it or not
+    pub(crate) fn last(handle: &SensorHandle, sensor_type: SensorType) -> Option<SensorReading> {
+        handle.state.0.lock().unwrap().last.get(&(sensor_type as i32)).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{last, sensor_service};
+    use super::*;
+    use std::thread::sleep;
+
+    fn start(service: &mut SensorService, sensor_type: SensorType, min_update_period_ms: i64) {
+        let mut request = SensorRequest::new();
+        request.set_type(sensor_type);
+        request.set_min_update_period(min_update_period_ms);
+
+        service.handle_message(Message::new_with_protobuf_message(2, false, request, SensorsMessageType::StartRequest as u16));
+    }
+
+    // Takes the responses and batches queued since the last call
+    fn sent(context: &ConnectionContext) -> (Vec<MessageStatus>, Vec<sensors::SensorBatch>) {
+        let mut responses = vec![];
+        let mut batches = vec![];
+
+        for (message, _) in context.commands().lock().unwrap().messages_to_send() {
+            assert_eq!(message.channel, 2);
+
+            if message.msg_type == SensorsMessageType::StartResponse as u16 {
+                responses.push(message.to_protobuf_message::<sensors::SensorResponse>().status());
+            } else if message.msg_type == SensorsMessageType::Event as u16 {
+                batches.push(message.to_protobuf_message());
+            }
+        }
+
+        (responses, batches)
+    }
+
+    fn rpms(batches: &[sensors::SensorBatch]) -> Vec<Vec<i32>> {
+        batches.iter().map(|batch| batch.rpm.iter().map(|rpm| rpm.rpm()).collect()).collect()
+    }
+
+    #[test]
+    fn unprovided_sensors() {
+        let (mut service, context) = sensor_service(&[SensorType::RPM]);
+        let handle = service.handle();
+
+        start(&mut service, SensorType::CarSpeed, 0);
+        assert_eq!(sent(&context), (vec![MessageStatus::Error], vec![]));
+
+        handle.push(SensorReading::Speed { speed: 1.0, cruise_engaged: None, cruise_set_speed: None });
+        assert_eq!(last(&handle, SensorType::CarSpeed), None);
+        assert_eq!(sent(&context), (vec![], vec![]));
+    }
+
+    #[test]
+    fn unsubscribed_sensors_are_held_back() {
+        let (mut service, context) = sensor_service(&[SensorType::RPM, SensorType::Gear]);
+        let handle = service.handle();
+
+        handle.push(SensorReading::Rpm(800));
+        handle.push(SensorReading::Rpm(900));
+        assert_eq!(sent(&context), (vec![], vec![]));
+
+        // The latest reading is sent right away when the phone starts the sensor
+        start(&mut service, SensorType::RPM, 0);
+        let (responses, batches) = sent(&context);
+        assert_eq!(responses, [MessageStatus::Ok]);
+        assert_eq!(rpms(&batches), [[900]]);
+
+        // Readings of other sensors in the same batch are dropped
+        handle.push_batch(vec![SensorReading::Rpm(1000), SensorReading::Gear(Gear::Drive)]);
+        let (_, batches) = sent(&context);
+        assert_eq!(rpms(&batches), [[1000]]);
+        assert!(batches[0].gear_data.is_empty());
+    }
+
+    #[test]
+    fn driving_status_starts_unrestricted() {
+        let (mut service, context) = sensor_service(&[SensorType::DrivingStatus]);
+
+        start(&mut service, SensorType::DrivingStatus, 0);
+        let (_, batches) = sent(&context);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].driving_status[0].status(), DrivingStatus::Unrestricted as i32);
+    }
+
+    #[test]
+    fn without_update_period_everything_is_sent() {
+        let (mut service, context) = sensor_service(&[SensorType::RPM]);
+        let handle = service.handle();
+
+        start(&mut service, SensorType::RPM, 0);
+        sent(&context);
+
+        handle.push(SensorReading::Rpm(1));
+        handle.push(SensorReading::Rpm(2));
+        handle.push(SensorReading::Rpm(3));
+        assert_eq!(rpms(&sent(&context).1), [[1], [2], [3]]);
+
+        // Nothing is left for the flush thread
+        sleep(Duration::from_millis(50));
+        assert_eq!(sent(&context), (vec![], vec![]));
+    }
+
+    #[test]
+    fn readings_are_coalesced() {
+        let (mut service, context) = sensor_service(&[SensorType::RPM]);
+        let handle = service.handle();
+
+        start(&mut service, SensorType::RPM, 200);
+        sent(&context);
+
+        handle.push(SensorReading::Rpm(1));
+        handle.push(SensorReading::Rpm(2));
+        handle.push(SensorReading::Rpm(3));
+        assert_eq!(rpms(&sent(&context).1), [[1]]);
+
+        // The flush thread sends the latest one once the period passed
+        sleep(Duration::from_millis(400));
+        assert_eq!(rpms(&sent(&context).1), [[3]]);
+
+        sleep(Duration::from_millis(300));
+        assert_eq!(sent(&context), (vec![], vec![]));
+    }
+
+    #[test]
+    fn flush_pending() {
+        let start = Instant::now();
+        let period = Duration::from_millis(100);
+
+        let mut state = SensorState {
+            sensors: vec![SensorType::RPM, SensorType::Gear],
+            last: BTreeMap::new(),
+            subscriptions: BTreeMap::new(),
+            running: true,
+        };
+        state.subscriptions.insert(SensorType::RPM as i32, Subscription {
+            min_update_period: period,
+            last_sent: Some(start),
+            pending: Some(SensorReading::Rpm(3)),
+        });
+        state.subscriptions.insert(SensorType::Gear as i32, Subscription {
+            min_update_period: period * 2,
+            last_sent: Some(start),
+            pending: Some(SensorReading::Gear(Gear::Park)),
+        });
+
+        let mut batch = sensors::SensorBatch::new();
+        assert_eq!(state.flush_pending(start + period / 2, &mut batch), Some(start + period));
+        assert_eq!(batch.compute_size(), 0);
+
+        assert_eq!(state.flush_pending(start + period, &mut batch), Some(start + period * 2));
+        assert_eq!(rpms(&[batch]), [[3]]);
+
+        let mut batch = sensors::SensorBatch::new();
+        assert_eq!(state.flush_pending(start + period * 2, &mut batch), None);
+        assert!(batch.rpm.is_empty());
+        assert_eq!(batch.gear_data[0].gear(), Gear::Park);
+
+        // Nothing pending anymore
+        let mut batch = sensors::SensorBatch::new();
+        assert_eq!(state.flush_pending(start + period * 10, &mut batch), None);
+        assert_eq!(batch.compute_size(), 0);
+    }
+}
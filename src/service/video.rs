@@ -1,40 +1,106 @@
 use crate::connection::ConnectionContext;
 use crate::message::{MediaMessageType, Message};
-use crate::protobuf::control::service::media_sink_service::video_configuration::{VideoCodecResolutionType, VideoFrameRateType};
 use crate::protobuf::control::service::media_sink_service::VideoConfiguration;
 use crate::protobuf::control::service::MediaSinkService;
 use crate::protobuf::media;
 use crate::protobuf::media::config::ConfigStatus;
-use crate::protobuf::media::{AudioStreamType, MediaCodecType, MediaSetupRequest, VideoFocusMode, VideoFocusRequestNotification};
+use crate::protobuf::media::{AudioStreamType, MediaCodecType, MediaSetupRequest, VideoFocusRequestNotification};
+use crate::recorder::VideoRecorder;
 use crate::service::Service;
 use protobuf::Message as ProtoMessage;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 
+pub use crate::protobuf::control::service::media_sink_service::video_configuration::{VideoCodecResolutionType, VideoFrameRateType};
+pub use crate::protobuf::control::service::media_sink_service::DisplayType;
+pub use crate::protobuf::media::VideoFocusMode;
+
+pub struct VideoServiceConfig {
+    pub display_id: u32,
+    pub display_type: DisplayType,
+    pub resolution: VideoCodecResolutionType,
+    pub frame_rate: VideoFrameRateType,
+    pub margin_width: u32,
+    pub margin_height: u32,
+    pub density: u32,
+}
+
+impl VideoServiceConfig {
+    // Size of the encoded video in pixels, including the margins
+    pub fn video_size(&self) -> (u32, u32) {
+        match self.resolution {
+            VideoCodecResolutionType::_800x480 => (800, 480),
+            VideoCodecResolutionType::_1280x720 => (1280, 720),
+            VideoCodecResolutionType::_1920x1080 => (1920, 1080),
+            VideoCodecResolutionType::_2560x1440 => (2560, 1440),
+            VideoCodecResolutionType::_3840x2160 => (3840, 2160),
+            VideoCodecResolutionType::_720x1280 => (720, 1280),
+            VideoCodecResolutionType::_1080x1920 => (1080, 1920),
+            VideoCodecResolutionType::_1440x2560 => (1440, 2560),
+            VideoCodecResolutionType::_2160x3840 => (2160, 3840),
+        }
+    }
+}
+
+impl Default for VideoServiceConfig {
+    fn default() -> Self {
+        Self {
+            display_id: 0,
+            display_type: DisplayType::Main,
+            resolution: VideoCodecResolutionType::_1280x720,
+            frame_rate: VideoFrameRateType::_30,
+            margin_width: 0,
+            margin_height: 0,
+            density: 216,
+        }
+    }
+}
+
+// Decides which focus to grant when the phone requests one for this display
+pub type VideoFocusPolicy = Box<dyn Fn(VideoFocusMode) -> VideoFocusMode + Send>;
+
 pub struct VideoService {
+    config: VideoServiceConfig,
     session_id: Option<i32>,
+    focus_policy: VideoFocusPolicy,
     pub buffer_sender: Sender<Vec<u8>>,
     pub infos: Vec<u8>,
+    recorder: Option<VideoRecorder>,
     context: Arc<ConnectionContext>,
 }
 
 impl VideoService {
-    pub fn new(buffer_sender: Sender<Vec<u8>>, context: Arc<ConnectionContext>) -> Self {
+    pub fn new(config: VideoServiceConfig, buffer_sender: Sender<Vec<u8>>, context: Arc<ConnectionContext>) -> Self {
         Self {
+            config,
             session_id: None,
+            // The phone gets the focus it asks for
+            focus_policy: Box::new(|requested| requested),
             buffer_sender,
             infos: vec![],
+            recorder: None,
             context,
         }
     }
 
+    pub fn with_recorder(mut self, recorder: VideoRecorder) -> Self {
+        self.recorder = Some(recorder);
+
+        self
+    }
+
+    pub fn with_focus_policy<F: Fn(VideoFocusMode) -> VideoFocusMode + Send + 'static>(mut self, policy: F) -> Self {
+        self.focus_policy = Box::new(policy);
+
+        self
+    }
+
     fn handle_media_setup_request(&mut self, message: Message) {
-        println!("Handling media setup request");
+        println!("Handling media setup request (display {})", self.config.display_id);
 
         let data = MediaSetupRequest::parse_from_bytes(message.data.as_slice()).unwrap();
-        let type_ = data.type_;
 
-        if let Some(type_) = type_ {
+        if data.type_.is_some() {
             let mut config = media::Config::new();
             config.set_status(ConfigStatus::HeadUnit);
             config.set_max_unacked(1);
@@ -62,12 +128,14 @@ impl VideoService {
     }
 
     pub fn handle_video_focus_request(&mut self, message: Message) {
-        println!("VideoFocusRequest"); // TODO
-
         let data = VideoFocusRequestNotification::parse_from_bytes(message.data.as_slice()).unwrap();
 
+        let granted = (self.focus_policy)(data.mode());
+
+        println!("VideoFocusRequest (display {}): {:?}, granted {:?}", self.config.display_id, data.mode(), granted);
+
         let mut config = media::VideoFocusNotification::new();
-        config.set_mode(VideoFocusMode::Focused);
+        config.set_mode(granted);
         config.set_unsolicited(false);
 
         let mut commands = self.context.commands().lock().unwrap();
@@ -84,11 +152,25 @@ impl VideoService {
 
         self.session_id = req.session_id;
 
-        println!("MediaStartRequest Video: {:?}", req.session_id)
+        println!("MediaStartRequest Video (display {}): {:?}", self.config.display_id, req.session_id)
     }
 
     pub fn handel_data_request(&mut self, message: Message) {
-        self.send_media_ack();
+        self.send_media_ack(message.channel);
+
+        if message.data.len() < 8 {
+            println!("VideoChannel {}: Dropping media data without a timestamp ({} bytes)", message.channel, message.data.len());
+            return;
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            let timestamp = u64::from_be_bytes(message.data[..8].try_into().unwrap());
+
+            if let Err(e) = recorder.write_frame(timestamp, &message.data[8..]) {
+                println!("Video recording failed, stopping: {:?}", e);
+                self.recorder = None;
+            }
+        }
 
         let mut buffer = self.infos.clone();
         buffer.append(&mut message.data[8..].to_vec());
@@ -97,20 +179,26 @@ impl VideoService {
     }
 
     pub fn handle_codec_config_request(&mut self, message: Message) {
-        self.send_media_ack();
+        self.send_media_ack(message.channel);
+
+        if let Some(recorder) = self.recorder.as_mut()
+            && let Err(e) = recorder.write_codec_config(&message.data) {
+            println!("Video recording failed, stopping: {:?}", e);
+            self.recorder = None;
+        }
 
         self.infos = message.data.to_vec();
     }
 
-    pub fn send_media_ack(&mut self) {
-        if self.session_id.is_some() {
+    pub fn send_media_ack(&mut self, channel: u8) {
+        if let Some(session_id) = self.session_id {
             let mut ack = media::Ack::new();
-            ack.set_session_id(self.session_id.unwrap());
+            ack.set_session_id(session_id);
             ack.set_ack(1);
 
             let mut commands = self.context.commands().lock().unwrap();
             commands.send_message(Message::new_with_protobuf_message(
-                2,
+                channel,
                 false,
                 ack,
                 MediaMessageType::Ack as u16
@@ -120,6 +208,10 @@ impl VideoService {
 }
 
 impl Service for VideoService {
+    fn on_add(&mut self, channel_id: u8) {
+        self.context.register_display(self.config.display_id, channel_id);
+    }
+
     fn protobuf_descriptor(&self, channel_id: u8) -> crate::protobuf::control::Service {
         let mut service = crate::protobuf::control::Service::new();
         service.id = Some(channel_id as u32);
@@ -128,13 +220,15 @@ impl Service for VideoService {
         media_sink.set_available_type(MediaCodecType::MediaCodecVideoH264BP);
         media_sink.set_audio_type(AudioStreamType::None);
         media_sink.available_while_in_call = Some(true);
+        media_sink.display_id = Some(self.config.display_id);
+        media_sink.set_display_type(self.config.display_type);
 
         let mut video_configuration = VideoConfiguration::new();
-        video_configuration.margin_height = Some(0);
-        video_configuration.margin_width = Some(0);
-        video_configuration.set_codec_resolution(VideoCodecResolutionType::_1280x720);
-        video_configuration.set_frame_rate(VideoFrameRateType::_30);
-        video_configuration.density = Some(216);
+        video_configuration.margin_height = Some(self.config.margin_height);
+        video_configuration.margin_width = Some(self.config.margin_width);
+        video_configuration.set_codec_resolution(self.config.resolution);
+        video_configuration.set_frame_rate(self.config.frame_rate);
+        video_configuration.density = Some(self.config.density);
 
         media_sink.video_configs.push(video_configuration);
 
@@ -166,3 +260,79 @@ impl Service for VideoService {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn media_data(data: Vec<u8>) -> Message {
+        Message {
+            channel: 3,
+            is_control: false,
+            length: data.len() as u16,
+            msg_type: MediaMessageType::MediaData as u16,
+            data,
+        }
+    }
+
+    #[test]
+    fn media_data_is_forwarded() {
+        let (sender, receiver) = channel();
+        let mut service = VideoService::new(VideoServiceConfig::default(), sender, Arc::new(ConnectionContext::new()));
+        service.infos = vec![0, 0, 0, 1, 0x67];
+
+        service.handel_data_request(media_data(vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0x65]));
+        assert_eq!(receiver.try_recv(), Ok(vec![0, 0, 0, 1, 0x67, 0, 0, 0, 1, 0x65]));
+    }
+
+    #[test]
+    fn short_media_data_is_dropped() {
+        let (sender, receiver) = channel();
+        let mut service = VideoService::new(VideoServiceConfig::default(), sender, Arc::new(ConnectionContext::new()));
+
+        service.handel_data_request(media_data(vec![]));
+        service.handel_data_request(media_data(vec![0, 0, 0, 1, 0x65]));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    fn focus_request(mode: VideoFocusMode) -> Message {
+        let mut request = VideoFocusRequestNotification::new();
+        request.set_mode(mode);
+        request.set_reason(media::video_focus_request_notification::VideoFocusReason::Reason1);
+
+        Message::new_with_protobuf_message(3, false, request, MediaMessageType::VideoFocusRequestNotification as u16)
+    }
+
+    fn focus_notifications(context: &ConnectionContext) -> Vec<VideoFocusMode> {
+        context.commands().lock().unwrap().messages_to_send().into_iter()
+            .map(|(message, _)| message.to_protobuf_message::<media::VideoFocusNotification>().mode())
+            .collect()
+    }
+
+    #[test]
+    fn focus_policy() {
+        let context = Arc::new(ConnectionContext::new());
+        let mut service = VideoService::new(VideoServiceConfig::default(), channel().0, Arc::clone(&context));
+
+        service.handle_video_focus_request(focus_request(VideoFocusMode::Unfocused));
+        assert_eq!(focus_notifications(&context), [VideoFocusMode::Unfocused]);
+
+        let mut service = service.with_focus_policy(|_| VideoFocusMode::Focused);
+        service.handle_video_focus_request(focus_request(VideoFocusMode::Unfocused));
+        assert_eq!(focus_notifications(&context), [VideoFocusMode::Focused]);
+    }
+
+    #[test]
+    fn display_is_registered_when_added() {
+        let context = Arc::new(ConnectionContext::new());
+        let config = VideoServiceConfig { display_id: 1, ..Default::default() };
+        let mut service = VideoService::new(config, channel().0, Arc::clone(&context));
+
+        service.protobuf_descriptor(5);
+        assert_eq!(context.display_channel_id(1), None);
+
+        service.on_add(5);
+        assert_eq!(context.display_channel_id(1), Some(5));
+    }
+}